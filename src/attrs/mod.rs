@@ -0,0 +1,574 @@
+use syn::{Attribute, Error, Expr, ExprPath, Ident, Lit, Meta, MetaList, NestedMeta, Path, Type};
+
+/// The JSON value a unit variant is written as.
+#[derive(Clone)]
+pub enum VariantValue {
+    Str(String),
+    Int(i64),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DisplayFormat {
+    Compact,
+    Pretty,
+    Redacted,
+}
+
+// Conventions accepted by `#[json(rename_all = "...")]`.
+const RENAME_RULES: &[&str] = &[
+    "lowercase",
+    "UPPERCASE",
+    "PascalCase",
+    "camelCase",
+    "snake_case",
+    "SCREAMING_SNAKE_CASE",
+    "kebab-case",
+    "SCREAMING-KEBAB-CASE",
+];
+
+/// Options read from `#[json(...)]` on the struct or enum itself.
+pub struct ContainerAttrs {
+    pub display: Option<DisplayFormat>,
+    pub repr_int: bool,
+    pub patch: bool,
+    pub mongo: bool,
+    pub indexes: Vec<IndexAttrs>,
+    pub rename_all: Option<String>,
+    pub omit_none: bool,
+    pub omit_empty: bool,
+    pub computed: Vec<ComputedAttrs>,
+    /// Adds `as_json`, a `Serialize` view of the `to_json` output. The type's
+    /// own `Serialize`, and so what the mongodb driver stores, is left alone.
+    pub serialize: bool,
+    pub crate_path: Option<Path>,
+    pub type_tag: Option<TypeTag>,
+    pub envelope: Option<Envelope>,
+    pub context: Option<Type>,
+}
+
+impl ContainerAttrs {
+    pub fn from_attrs(name: &Ident, attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut container = ContainerAttrs {
+            display: Some(DisplayFormat::Compact),
+            repr_int: false,
+            patch: false,
+            mongo: false,
+            indexes: vec![],
+            rename_all: None,
+            omit_none: false,
+            omit_empty: false,
+            computed: vec![],
+            serialize: false,
+            crate_path: None,
+            type_tag: None,
+            envelope: None,
+            context: None,
+        };
+
+        for meta in json_metas(attrs)? {
+            let key = meta_key(&meta)?;
+            match (key.as_str(), &meta) {
+                ("no_display", Meta::Path(_)) => container.display = None,
+                ("display", Meta::NameValue(m)) => {
+                    container.display = Some(match lit_str(&m.lit)?.as_str() {
+                        "compact" => DisplayFormat::Compact,
+                        "pretty" => DisplayFormat::Pretty,
+                        "redacted" => DisplayFormat::Redacted,
+                        _ => {
+                            return Err(Error::new_spanned(
+                                &m.lit,
+                                "expected \"compact\", \"pretty\" or \"redacted\"",
+                            ))
+                        }
+                    });
+                }
+                ("patch", Meta::Path(_)) => container.patch = true,
+                ("mongo", Meta::Path(_)) => container.mongo = true,
+                ("serialize", Meta::Path(_)) => container.serialize = true,
+                ("type_tag", Meta::NameValue(m)) => {
+                    container.type_tag = Some(TypeTag {
+                        key: lit_str(&m.lit)?,
+                        value: name.to_string(),
+                    });
+                }
+                ("type_tag", Meta::List(m)) => {
+                    container.type_tag = Some(TypeTag::from_list(name, m)?)
+                }
+                ("envelope", Meta::NameValue(m)) => {
+                    container.envelope = Some(Envelope {
+                        key: lit_str(&m.lit)?,
+                        siblings: vec![],
+                    });
+                }
+                ("envelope", Meta::List(m)) => container.envelope = Some(Envelope::from_list(m)?),
+                ("crate", Meta::NameValue(m)) => {
+                    let path = lit_str(&m.lit)?;
+                    container.crate_path =
+                        Some(syn::parse_str(&path).map_err(|err| Error::new_spanned(&m.lit, err))?);
+                }
+                ("context", Meta::NameValue(m)) => {
+                    let ty = lit_str(&m.lit)?;
+                    container.context =
+                        Some(syn::parse_str(&ty).map_err(|err| Error::new_spanned(&m.lit, err))?);
+                }
+                ("omit_none", Meta::Path(_)) => container.omit_none = true,
+                ("omit_empty", Meta::Path(_)) => container.omit_empty = true,
+                ("index", Meta::List(m)) => container.indexes.push(IndexAttrs::from_list(m, None)?),
+                ("computed", Meta::List(m)) => {
+                    container.computed.push(ComputedAttrs::from_list(m)?)
+                }
+                ("rename_all", Meta::NameValue(m)) => {
+                    let convention = lit_str(&m.lit)?;
+                    if !RENAME_RULES.contains(&convention.as_str()) {
+                        return Err(Error::new_spanned(
+                            &m.lit,
+                            format!("expected one of: {}", RENAME_RULES.join(", ")),
+                        ));
+                    }
+                    container.rename_all = Some(convention);
+                }
+                ("repr", Meta::NameValue(m)) => {
+                    container.repr_int = match lit_str(&m.lit)?.as_str() {
+                        "int" => true,
+                        "string" => false,
+                        _ => {
+                            return Err(Error::new_spanned(
+                                &m.lit,
+                                "expected \"int\" or \"string\"",
+                            ))
+                        }
+                    };
+                }
+                _ => return Err(unknown_attr(&meta, &key)),
+            }
+        }
+
+        Ok(container)
+    }
+}
+
+/// A key naming the type, written before the fields.
+pub struct TypeTag {
+    pub key: String,
+    pub value: String,
+}
+
+impl TypeTag {
+    fn from_list(name: &Ident, list: &MetaList) -> syn::Result<Self> {
+        let mut key = None;
+        let mut value = name.to_string();
+
+        for nested_meta in &list.nested {
+            match nested_meta {
+                NestedMeta::Meta(Meta::NameValue(m)) if m.path.is_ident("key") => {
+                    key = Some(lit_str(&m.lit)?)
+                }
+                NestedMeta::Meta(Meta::NameValue(m)) if m.path.is_ident("value") => {
+                    value = lit_str(&m.lit)?
+                }
+                other => return Err(Error::new_spanned(other, "expected `key` or `value`")),
+            }
+        }
+
+        match key {
+            Some(key) => Ok(TypeTag { key, value }),
+            None => Err(Error::new_spanned(list, "type_tag(...) needs a `key`")),
+        }
+    }
+}
+
+/// A key the output is wrapped under, next to static sibling keys.
+pub struct Envelope {
+    pub key: String,
+    pub siblings: Vec<(String, Lit)>,
+}
+
+impl Envelope {
+    fn from_list(list: &MetaList) -> syn::Result<Self> {
+        let mut key = None;
+        let mut siblings = vec![];
+
+        for nested_meta in &list.nested {
+            match nested_meta {
+                NestedMeta::Meta(Meta::NameValue(m)) if m.path.is_ident("key") => {
+                    key = Some(lit_str(&m.lit)?)
+                }
+                NestedMeta::Meta(Meta::List(m)) if m.path.is_ident("siblings") => {
+                    for nested_meta in &m.nested {
+                        match nested_meta {
+                            NestedMeta::Meta(meta @ Meta::NameValue(m)) => {
+                                siblings.push((meta_key(meta)?, m.lit.clone()))
+                            }
+                            other => {
+                                return Err(Error::new_spanned(other, "expected `key = value`"))
+                            }
+                        }
+                    }
+                }
+                other => {
+                    return Err(Error::new_spanned(
+                        other,
+                        "expected `key` or `siblings(...)`",
+                    ))
+                }
+            }
+        }
+
+        match key {
+            Some(key) => Ok(Envelope { key, siblings }),
+            None => Err(Error::new_spanned(list, "envelope(...) needs a `key`")),
+        }
+    }
+}
+
+/// Where a computed key is placed among the field keys.
+pub enum Position {
+    End,
+    Before(Ident),
+    After(Ident),
+}
+
+/// An output key computed by a method, declared with `computed(...)`.
+pub struct ComputedAttrs {
+    pub name: String,
+    pub with: ExprPath,
+    pub position: Position,
+}
+
+impl ComputedAttrs {
+    fn from_list(list: &MetaList) -> syn::Result<Self> {
+        let mut name = None;
+        let mut with = None;
+        let mut position = Position::End;
+
+        for nested_meta in &list.nested {
+            let m = match nested_meta {
+                NestedMeta::Meta(Meta::NameValue(m)) => m,
+                other => return Err(Error::new_spanned(other, "expected `option = \"...\"`")),
+            };
+            let key = meta_key(&Meta::NameValue(m.clone()))?;
+            let value = lit_str(&m.lit)?;
+            let parse_error = |err: Error| Error::new_spanned(&m.lit, err);
+            match key.as_str() {
+                "name" => name = Some(value),
+                "with" => with = Some(syn::parse_str(&value).map_err(parse_error)?),
+                "before" => {
+                    position = Position::Before(syn::parse_str(&value).map_err(parse_error)?)
+                }
+                "after" => position = Position::After(syn::parse_str(&value).map_err(parse_error)?),
+                _ => {
+                    return Err(Error::new_spanned(
+                        m,
+                        format!("unknown computed option `{}`", key),
+                    ))
+                }
+            }
+        }
+
+        match (name, with) {
+            (Some(name), Some(with)) => Ok(ComputedAttrs {
+                name,
+                with,
+                position,
+            }),
+            _ => Err(Error::new_spanned(
+                list,
+                "computed(...) needs both `name` and `with`",
+            )),
+        }
+    }
+}
+
+/// Options read from `#[json]` and `#[json(...)]` on a struct field.
+pub struct FieldAttrs {
+    pub nested: bool,
+    pub rename: Option<String>,
+    pub omit_none: bool,
+    pub omit_empty: bool,
+    pub default_for_none: Option<Lit>,
+    pub reference: bool,
+    pub raw: bool,
+    /// Checked by `to_json_with` only; every other output still has the field.
+    pub condition: Option<Expr>,
+    pub since: Option<u32>,
+    pub until: Option<u32>,
+    pub rename_in: Vec<(u32, String)>,
+    pub indexes: Vec<IndexAttrs>,
+}
+
+impl FieldAttrs {
+    pub fn from_attrs(ident: &Ident, attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut field = FieldAttrs {
+            nested: attrs.iter().any(|attr| {
+                attr.path.is_ident("json") && matches!(attr.parse_meta(), Ok(Meta::Path(_)))
+            }),
+            rename: None,
+            omit_none: false,
+            omit_empty: false,
+            default_for_none: None,
+            reference: false,
+            raw: false,
+            condition: None,
+            since: None,
+            until: None,
+            rename_in: vec![],
+            indexes: vec![],
+        };
+
+        for meta in json_metas(attrs)? {
+            let key = meta_key(&meta)?;
+            match (key.as_str(), &meta) {
+                ("nested", Meta::Path(_)) => field.nested = true,
+                ("rename", Meta::NameValue(m)) => field.rename = Some(lit_str(&m.lit)?),
+                ("reference", Meta::Path(_)) => field.reference = true,
+                ("raw", Meta::Path(_)) => field.raw = true,
+                ("if", Meta::NameValue(m)) => {
+                    let condition = lit_str(&m.lit)?;
+                    field.condition = Some(
+                        syn::parse_str(&condition)
+                            .map_err(|err| Error::new_spanned(&m.lit, err))?,
+                    );
+                }
+                ("omit_none", Meta::Path(_)) => field.omit_none = true,
+                ("omit_empty", Meta::Path(_)) => field.omit_empty = true,
+                ("default_for_none", Meta::NameValue(m)) => {
+                    field.default_for_none = Some(m.lit.clone())
+                }
+                ("since", Meta::NameValue(m)) => field.since = Some(version(&m.lit)?),
+                ("until", Meta::NameValue(m)) => field.until = Some(version(&m.lit)?),
+                ("rename_in", Meta::List(m)) => {
+                    for nested_meta in &m.nested {
+                        field.rename_in.push(match nested_meta {
+                            NestedMeta::Meta(Meta::NameValue(m)) => {
+                                let version = m
+                                    .path
+                                    .get_ident()
+                                    .and_then(|ident| {
+                                        ident.to_string().strip_prefix('v')?.parse().ok()
+                                    })
+                                    .ok_or_else(|| {
+                                        Error::new_spanned(&m.path, "expected a version like `v1`")
+                                    })?;
+                                if field.rename_in.iter().any(|(other, _)| *other == version) {
+                                    return Err(Error::new_spanned(
+                                        &m.path,
+                                        format!("v{} is renamed more than once", version),
+                                    ));
+                                }
+                                (version, lit_str(&m.lit)?)
+                            }
+                            other => {
+                                return Err(Error::new_spanned(other, "expected `v1 = \"name\"`"))
+                            }
+                        });
+                    }
+                }
+                ("index", Meta::Path(_)) => field.indexes.push(IndexAttrs::single(ident, 1)),
+                ("index", Meta::List(m)) => {
+                    field.indexes.push(IndexAttrs::from_list(m, Some(ident))?)
+                }
+                ("unique", Meta::Path(_)) => field.indexes.push(IndexAttrs {
+                    unique: true,
+                    ..IndexAttrs::single(ident, 1)
+                }),
+                _ => return Err(unknown_attr(&meta, &key)),
+            }
+        }
+
+        if let (Some(since), Some(until)) = (field.since, field.until) {
+            if since > until {
+                return Err(Error::new_spanned(
+                    ident,
+                    format!("since = {} comes after until = {}", since, until),
+                ));
+            }
+        }
+        if let (true, Some(lit)) = (field.omit_none, &field.default_for_none) {
+            return Err(Error::new_spanned(
+                lit,
+                "omit_none and default_for_none cannot be used together",
+            ));
+        }
+        Ok(field)
+    }
+}
+
+/// A MongoDB index declared with `index(...)` or `unique`.
+/// Keys name Rust fields; the generator resolves them to stored names.
+pub struct IndexAttrs {
+    pub keys: Vec<(Ident, i32)>,
+    pub unique: bool,
+    pub ttl: Option<u64>,
+    pub name: Option<String>,
+}
+
+impl IndexAttrs {
+    fn single(ident: &Ident, order: i32) -> Self {
+        IndexAttrs {
+            keys: vec![(ident.clone(), order)],
+            unique: false,
+            ttl: None,
+            name: None,
+        }
+    }
+
+    // On a field the index covers that field; on the container it lists them in `fields(...)`.
+    fn from_list(list: &MetaList, field: Option<&Ident>) -> syn::Result<Self> {
+        let mut index = IndexAttrs {
+            keys: field
+                .map(|ident| vec![(ident.clone(), 1)])
+                .unwrap_or_default(),
+            unique: false,
+            ttl: None,
+            name: None,
+        };
+
+        for nested_meta in &list.nested {
+            let meta = match nested_meta {
+                NestedMeta::Meta(meta) => meta,
+                NestedMeta::Lit(lit) => {
+                    return Err(Error::new_spanned(lit, "expected an index option"))
+                }
+            };
+            let key = meta_key(meta)?;
+            match (key.as_str(), meta) {
+                ("unique", Meta::Path(_)) => index.unique = true,
+                ("ttl", Meta::NameValue(m)) => match &m.lit {
+                    Lit::Int(lit_int) => index.ttl = Some(lit_int.base10_parse()?),
+                    lit => return Err(Error::new_spanned(lit, "expected a number of seconds")),
+                },
+                ("name", Meta::NameValue(m)) => index.name = Some(lit_str(&m.lit)?),
+                ("order", Meta::NameValue(m)) if field.is_some() => {
+                    index.keys[0].1 = index_order(&m.lit)?;
+                }
+                ("fields", Meta::List(m)) if field.is_none() => {
+                    for nested_meta in &m.nested {
+                        index.keys.push(match nested_meta {
+                            NestedMeta::Meta(Meta::Path(path)) => match path.get_ident() {
+                                Some(ident) => (ident.clone(), 1),
+                                None => {
+                                    return Err(Error::new_spanned(path, "expected a field name"))
+                                }
+                            },
+                            NestedMeta::Meta(Meta::NameValue(m)) => match m.path.get_ident() {
+                                Some(ident) => (ident.clone(), index_order(&m.lit)?),
+                                None => {
+                                    return Err(Error::new_spanned(
+                                        &m.path,
+                                        "expected a field name",
+                                    ))
+                                }
+                            },
+                            other => {
+                                return Err(Error::new_spanned(other, "expected a field name"))
+                            }
+                        });
+                    }
+                }
+                _ => return Err(unknown_attr(meta, &key)),
+            }
+        }
+
+        if index.keys.is_empty() {
+            return Err(Error::new_spanned(list, "index(...) needs fields(...)"));
+        }
+        Ok(index)
+    }
+}
+
+fn index_order(lit: &Lit) -> syn::Result<i32> {
+    match lit_str(lit)?.as_str() {
+        "asc" => Ok(1),
+        "desc" => Ok(-1),
+        _ => Err(Error::new_spanned(lit, "expected \"asc\" or \"desc\"")),
+    }
+}
+
+/// Options read from `#[json(...)]` on an enum variant.
+pub struct VariantAttrs {
+    pub other: bool,
+    pub value: Option<VariantValue>,
+}
+
+impl VariantAttrs {
+    pub fn from_attrs(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut variant = VariantAttrs {
+            other: false,
+            value: None,
+        };
+
+        for meta in json_metas(attrs)? {
+            let key = meta_key(&meta)?;
+            match (key.as_str(), &meta) {
+                ("other", Meta::Path(_)) => variant.other = true,
+                ("value", Meta::NameValue(m)) => {
+                    variant.value = Some(match &m.lit {
+                        Lit::Str(lit_str) => VariantValue::Str(lit_str.value()),
+                        Lit::Int(lit_int) => VariantValue::Int(lit_int.base10_parse()?),
+                        lit => {
+                            return Err(Error::new_spanned(
+                                lit,
+                                "expected a string or integer literal",
+                            ))
+                        }
+                    });
+                }
+                _ => return Err(unknown_attr(&meta, &key)),
+            }
+        }
+
+        Ok(variant)
+    }
+}
+
+// Every item inside the `#[json(...)]` attributes; a bare `#[json]` contributes nothing.
+pub fn json_metas(attrs: &[Attribute]) -> syn::Result<Vec<Meta>> {
+    let mut metas = vec![];
+    for attr in attrs {
+        if !attr.path.is_ident("json") {
+            continue;
+        }
+        match attr.parse_meta()? {
+            Meta::Path(_) => {}
+            Meta::List(meta_list) => {
+                for nested_meta in meta_list.nested {
+                    match nested_meta {
+                        NestedMeta::Meta(meta) => metas.push(meta),
+                        NestedMeta::Lit(lit) => {
+                            return Err(Error::new_spanned(lit, "expected a json option"))
+                        }
+                    }
+                }
+            }
+            meta @ Meta::NameValue(_) => {
+                return Err(Error::new_spanned(meta, "expected #[json] or #[json(...)]"))
+            }
+        }
+    }
+    Ok(metas)
+}
+
+pub fn meta_key(meta: &Meta) -> syn::Result<String> {
+    meta.path()
+        .get_ident()
+        .map(|ident| ident.to_string())
+        .ok_or_else(|| Error::new_spanned(meta.path(), "expected a json option"))
+}
+
+pub fn lit_str(lit: &Lit) -> syn::Result<String> {
+    match lit {
+        Lit::Str(lit_str) => Ok(lit_str.value()),
+        _ => Err(Error::new_spanned(lit, "expected a string literal")),
+    }
+}
+
+fn version(lit: &Lit) -> syn::Result<u32> {
+    match lit {
+        Lit::Int(lit_int) => lit_int.base10_parse(),
+        _ => Err(Error::new_spanned(lit, "expected a version number")),
+    }
+}
+
+pub fn unknown_attr(meta: &Meta, key: &str) -> Error {
+    Error::new_spanned(meta, format!("unknown or malformed json option `{}`", key))
+}
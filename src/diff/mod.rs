@@ -0,0 +1,168 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::Type;
+
+use crate::attrs::{ComputedAttrs, ContainerAttrs};
+use crate::fields::{JsonField, Mode};
+use crate::types::{leaf_type, probe, to_value, Scalar, Shape};
+use crate::utils::{crate_path, pointer_token};
+
+// Compares `old` and `new` (references to values of type `shape`) and records
+// every difference under `path`, recursing into nested ToJson types.
+fn diff_shape(shape: &Shape, leaf_ty: &Type, old: TokenStream, new: TokenStream) -> TokenStream {
+    let krate = crate_path();
+    let whole = {
+        let old_value = to_value(shape, old.clone());
+        let new_value = to_value(shape, new.clone());
+        quote! {
+            #krate::__private::push_change(changes, path, #old_value, #new_value);
+        }
+    };
+
+    // Unclassified leaves may still derive ToJson, which only the probe can tell.
+    let is_plain = shape.leaf() == Scalar::Other;
+    if shape.leaf() != Scalar::Nested && !is_plain {
+        return whole;
+    }
+
+    match shape {
+        Shape::Scalar(_) if is_plain => probe(
+            quote!(Probe::<#leaf_ty>::new()),
+            quote!(diff(#old, #new, &path, changes)),
+        ),
+        Shape::Scalar(_) => quote! {
+            (#old).__json_diff_into(#new, &path, changes);
+        },
+        Shape::Box(inner) => diff_shape(inner, leaf_ty, quote!(&**#old), quote!(&**#new)),
+        Shape::Option(inner) => {
+            let inner = diff_shape(inner, leaf_ty, quote!(old), quote!(new));
+            quote! {
+                match (#old, #new) {
+                    (Some(old), Some(new)) => { #inner }
+                    _ => { #whole }
+                }
+            }
+        }
+        Shape::Vec(inner) => {
+            let is_node = if is_plain {
+                probe(quote!(Probe::<#leaf_ty>::new()), quote!(is_node()))
+            } else {
+                quote!(true)
+            };
+            let inner = diff_shape(inner, leaf_ty, quote!(old), quote!(new));
+            quote! {
+                if #is_node && (#old).len() == (#new).len() {
+                    for (index, (old, new)) in (#old).iter().zip((#new).iter()).enumerate() {
+                        let path = format!("{}/{}", path, index);
+                        #inner
+                    }
+                } else {
+                    #whole
+                }
+            }
+        }
+    }
+}
+
+pub fn expand_diff(fields: &[JsonField], container: &ContainerAttrs) -> TokenStream {
+    let krate = crate_path();
+    let field_diffs = fields.iter().map(|field| {
+        let ident = field.ident;
+        let token = field.pointer_token();
+        let mut diff = diff_shape(
+            &field.shape,
+            leaf_type(field.ty),
+            quote!(&self.#ident),
+            quote!(&other.#ident),
+        );
+
+        // Keys that can be left out are added or removed as a whole.
+        if let (Some(old), Some(new)) = (
+            field.emit(quote!(self), Mode::Value),
+            field.emit(quote!(other), Mode::Value),
+        ) {
+            let both = if field.default_for_none.is_some() {
+                quote! {
+                    (Some(old), Some(new)) => #krate::__private::push_change(changes, path, old, new),
+                }
+            } else {
+                quote! {
+                    (Some(_), Some(_)) => { #diff }
+                }
+            };
+            diff = quote! {
+                match (#old, #new) {
+                    #both
+                    (old, new) => #krate::__private::push_optional_change(changes, path, old, new),
+                }
+            };
+        }
+
+        quote! {
+            {
+                let path = format!("{}/{}", path, #token);
+                #diff
+            }
+        }
+    });
+
+    // Computed keys change whenever the fields they are derived from do.
+    let computed_diffs = container
+        .computed
+        .iter()
+        .map(|ComputedAttrs { name, with, .. }| {
+            let token = pointer_token(name);
+            quote! {
+                #krate::__private::push_change(
+                    changes,
+                    format!("{}/{}", path, #token),
+                    #krate::serde_json::Value::from(#with(self)),
+                    #krate::serde_json::Value::from(#with(other)),
+                );
+            }
+        });
+
+    // Paths point into the output, so they go through the envelope key.
+    let envelope = container.envelope.as_ref().map(|envelope| {
+        let token = pointer_token(&envelope.key);
+        quote! {
+            let path = &format!("{}/{}", path, #token);
+        }
+    });
+
+    quote! {
+        pub fn json_diff(&self, other: &Self) -> Vec<#krate::Change> {
+            let mut changes = Vec::new();
+            self.__json_diff_into(other, "", &mut changes);
+            changes
+        }
+        pub fn json_patch(&self, other: &Self) -> #krate::serde_json::Value {
+            #krate::json_patch(&self.json_diff(other))
+        }
+        #[doc(hidden)]
+        pub fn __json_diff_into(&self, other: &Self, path: &str, changes: &mut Vec<#krate::Change>) {
+            #envelope
+            #( #field_diffs )*
+            #( #computed_diffs )*
+        }
+    }
+}
+
+// Enums are compared as a whole through their JSON value.
+pub fn expand_enum_diff() -> TokenStream {
+    let krate = crate_path();
+    quote! {
+        pub fn json_diff(&self, other: &Self) -> Vec<#krate::Change> {
+            let mut changes = Vec::new();
+            self.__json_diff_into(other, "", &mut changes);
+            changes
+        }
+        pub fn json_patch(&self, other: &Self) -> #krate::serde_json::Value {
+            #krate::json_patch(&self.json_diff(other))
+        }
+        #[doc(hidden)]
+        pub fn __json_diff_into(&self, other: &Self, path: &str, changes: &mut Vec<#krate::Change>) {
+            #krate::__private::push_change(changes, path.to_string(), self.to_json(), other.to_json());
+        }
+    }
+}
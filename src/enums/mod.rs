@@ -0,0 +1,362 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{DataEnum, Error, Expr, ExprLit, ExprUnary, Fields, Ident, Lit, UnOp, Variant};
+
+use crate::attrs::{ContainerAttrs, DisplayFormat, VariantAttrs, VariantValue};
+use crate::diff::expand_enum_diff;
+use crate::merge::expand_enum_merge;
+use crate::mongo::{expand_enum_projection, expand_enum_schema};
+use crate::node::expand_node;
+use crate::paths::expand_enum_paths;
+use crate::pointer::expand_enum_pointer;
+use crate::ser::expand_enum_serialize;
+use crate::utils::{crate_path, get_variant_name};
+
+pub fn expand_enum(
+    name: &Ident,
+    data: &DataEnum,
+    container: &ContainerAttrs,
+    rename_all_convention: &Option<String>,
+) -> syn::Result<TokenStream> {
+    let krate = crate_path();
+    let is_unit = data
+        .variants
+        .iter()
+        .all(|variant| matches!(variant.fields, Fields::Unit));
+
+    if let Some(index) = container.indexes.first() {
+        return Err(Error::new_spanned(
+            &index.keys[0].0,
+            "indexes can only be declared on structs",
+        ));
+    }
+    if !container.computed.is_empty() {
+        return Err(Error::new_spanned(
+            name,
+            "computed keys can only be declared on structs",
+        ));
+    }
+    if container.type_tag.is_some() || container.envelope.is_some() {
+        return Err(Error::new_spanned(
+            name,
+            "type_tag and envelope can only be used on structs",
+        ));
+    }
+    if container.context.is_some() {
+        return Err(Error::new_spanned(
+            name,
+            "json(context) can only be used on structs",
+        ));
+    }
+    if container.mongo {
+        return Err(Error::new_spanned(
+            name,
+            "json(mongo) can only be used on structs",
+        ));
+    }
+    if matches!(
+        container.display,
+        Some(DisplayFormat::Pretty | DisplayFormat::Redacted)
+    ) {
+        return Err(Error::new_spanned(
+            name,
+            "json(display = \"pretty\") and json(display = \"redacted\") can only be used on structs",
+        ));
+    }
+    if container.rename_all.is_some() {
+        return Err(Error::new_spanned(
+            name,
+            "json(rename_all) can only be used on structs, use #[json(value = ...)] on variants",
+        ));
+    }
+
+    let body = if is_unit {
+        expand_unit_enum(name, data, container, rename_all_convention)?
+    } else if container.repr_int {
+        return Err(Error::new_spanned(
+            name,
+            "repr = \"int\" requires a unit enum",
+        ));
+    } else if container.serialize {
+        return Err(Error::new_spanned(
+            name,
+            "json(serialize) requires a unit enum, other enums use their serde impl",
+        ));
+    } else {
+        expand_serde_enum(name)
+    };
+
+    let display = container.display.map(|_| {
+        quote! {
+            impl ::std::fmt::Display for #name {
+                fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                    f.write_str(&self.get_string())
+                }
+            }
+        }
+    });
+
+    let diff = expand_enum_diff();
+    let merge = expand_enum_merge();
+    let pointer = expand_enum_pointer(name);
+    let projection = expand_enum_projection();
+    let paths = expand_enum_paths(name);
+    let node = expand_node(name, quote!(self.to_json()), None, None);
+    let serialize = container.serialize.then(|| expand_enum_serialize(name));
+
+    Ok(quote! {
+        #body
+
+        impl #name {
+            pub fn to_json_with_depth(&self, _max_depth: usize) -> #krate::serde_json::Value {
+                self.to_json()
+            }
+            pub fn to_json_version(&self, _version: u32) -> #krate::serde_json::Value {
+                self.to_json()
+            }
+            pub fn to_json_with<C: #krate::JsonContext + ?Sized>(&self, _ctx: &C) -> #krate::serde_json::Value {
+                self.to_json()
+            }
+            #[doc(hidden)]
+            pub fn __json_reference(&self) -> #krate::serde_json::Value {
+                self.to_json()
+            }
+
+            #diff
+            #merge
+            #projection
+        }
+
+        #node
+        #pointer
+        #display
+        #serialize
+        #paths
+    })
+}
+
+// Enums carrying data keep whatever their serde impl produces.
+fn expand_serde_enum(name: &Ident) -> TokenStream {
+    let krate = crate_path();
+    let schema = expand_enum_schema(None);
+
+    quote! {
+        impl #name {
+            pub fn get_string(&self) -> String {
+                self.to_json_string().replace("\"", "")
+            }
+            pub fn to_json_string(&self) -> String {
+                #krate::serde_json::to_string(self).expect("Failed to serialize to JSON")
+            }
+            pub fn to_json(&self) -> #krate::serde_json::Value {
+                #krate::serde_json::from_str(&self.to_json_string()).expect("Failed to deserialize from JSON")
+            }
+            pub fn from_json(value: &#krate::serde_json::Value) -> Result<Self, #krate::JsonError> {
+                Self::__from_json_at(value, "")
+            }
+            #[doc(hidden)]
+            pub fn __from_json_at(value: &#krate::serde_json::Value, path: &str) -> Result<Self, #krate::JsonError> {
+                #[allow(unused_imports)]
+                use #krate::__private::{DeserializeValue as _, NotDeserializable as _};
+                (&#krate::__private::FromJsonValue::<Self>::new(value, path)).deserialize_value()
+            }
+
+            #schema
+        }
+    }
+}
+
+// Discriminant of a variant, as written on it or counted up from the previous one.
+fn discriminant(variant: &Variant, next: i64) -> syn::Result<i64> {
+    match &variant.discriminant {
+        None => Ok(next),
+        Some((_, expr)) => match expr {
+            Expr::Lit(ExprLit {
+                lit: Lit::Int(lit_int),
+                ..
+            }) => lit_int.base10_parse(),
+            Expr::Unary(ExprUnary {
+                op: UnOp::Neg(_),
+                expr,
+                ..
+            }) => match &**expr {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Int(lit_int),
+                    ..
+                }) => lit_int.base10_parse::<i64>().map(|value| -value),
+                _ => Err(Error::new_spanned(
+                    expr,
+                    "use #[json(value = ...)] for this variant",
+                )),
+            },
+            _ => Err(Error::new_spanned(
+                expr,
+                "use #[json(value = ...)] for this variant",
+            )),
+        },
+    }
+}
+
+fn expand_unit_enum(
+    name: &Ident,
+    data: &DataEnum,
+    container: &ContainerAttrs,
+    rename_all_convention: &Option<String>,
+) -> syn::Result<TokenStream> {
+    let krate = crate_path();
+    let mut idents = vec![];
+    let mut names = vec![];
+    let mut json_values = vec![];
+    let mut bson_values = vec![];
+    let mut str_idents = vec![];
+    let mut str_values = vec![];
+    let mut int_idents = vec![];
+    let mut int_values = vec![];
+    let mut other: Option<&Ident> = None;
+    let mut next_discriminant = 0;
+
+    for variant in &data.variants {
+        let variant_attrs = VariantAttrs::from_attrs(&variant.attrs)?;
+        if variant_attrs.other {
+            if other.is_some() {
+                return Err(Error::new_spanned(
+                    variant,
+                    "only one variant can be marked #[json(other)]",
+                ));
+            }
+            other = Some(&variant.ident);
+        }
+
+        let value = match variant_attrs.value {
+            Some(value) => value,
+            None if container.repr_int => {
+                VariantValue::Int(discriminant(variant, next_discriminant)?)
+            }
+            None => VariantValue::Str(get_variant_name(variant, rename_all_convention)),
+        };
+        if let Ok(current) = discriminant(variant, next_discriminant) {
+            next_discriminant = current + 1;
+        }
+
+        // Two variants with one value could not both be read back.
+        let ident = &variant.ident;
+        let name = match &value {
+            VariantValue::Str(value) => value.clone(),
+            VariantValue::Int(value) => value.to_string(),
+        };
+        if let Some(index) = names.iter().position(|other| *other == name) {
+            return Err(Error::new_spanned(
+                variant,
+                format!("value `{}` is already used by `{}`", name, idents[index]),
+            ));
+        }
+        idents.push(ident);
+        names.push(name);
+        // MongoDB stores what `Serialize` writes, which is the serde name.
+        let serde_name = get_variant_name(variant, rename_all_convention);
+        bson_values.push(quote! { #serde_name });
+        match value {
+            VariantValue::Str(value) => {
+                json_values.push(quote! { #krate::serde_json::Value::String(#value.to_string()) });
+                str_idents.push(ident);
+                str_values.push(value);
+            }
+            VariantValue::Int(value) => {
+                json_values.push(quote! { #krate::serde_json::Value::from(#value) });
+                int_idents.push(ident);
+                int_values.push(value);
+            }
+        }
+    }
+
+    let invalid = |value: TokenStream| {
+        quote! {
+            #krate::ParseEnumError {
+                type_name: stringify!(#name),
+                value: #value.to_string(),
+                expected: Self::variant_names(),
+            }
+        }
+    };
+    // `from_json` reports where the value was found, `FromStr` only what it was.
+    let fallback = |value: TokenStream, at_path: bool| match other {
+        Some(other) => quote! { Ok(Self::#other) },
+        None if at_path => {
+            let invalid = invalid(value);
+            quote! { Err(#krate::JsonError::new(path, #invalid)) }
+        }
+        None => {
+            let invalid = invalid(value);
+            quote! { Err(#invalid) }
+        }
+    };
+    let schema = expand_enum_schema(Some(&bson_values));
+    let str_fallback = fallback(quote!(string), true);
+    let int_fallback = fallback(quote!(number), true);
+    let parse_fallback = fallback(quote!(value), false);
+    let invalid_value = invalid(quote!(value));
+
+    Ok(quote! {
+        impl #name {
+            pub const ALL: &'static [Self] = &[#( Self::#idents ),*];
+
+            pub fn variant_names() -> &'static [&'static str] {
+                &[#( #names ),*]
+            }
+            pub fn as_str(&self) -> &'static str {
+                match *self {
+                    #( Self::#idents => #names, )*
+                }
+            }
+            pub fn get_string(&self) -> String {
+                self.as_str().to_string()
+            }
+            pub fn to_json_string(&self) -> String {
+                self.to_json().to_string()
+            }
+            pub fn to_json(&self) -> #krate::serde_json::Value {
+                match *self {
+                    #( Self::#idents => #json_values, )*
+                }
+            }
+            pub fn from_json(value: &#krate::serde_json::Value) -> Result<Self, #krate::JsonError> {
+                Self::__from_json_at(value, "")
+            }
+            #[doc(hidden)]
+            pub fn __from_json_at(value: &#krate::serde_json::Value, path: &str) -> Result<Self, #krate::JsonError> {
+                match value {
+                    #krate::serde_json::Value::String(string) => match string.as_str() {
+                        #( #str_values => Ok(Self::#str_idents), )*
+                        _ => #str_fallback,
+                    },
+                    #krate::serde_json::Value::Number(number) => match number.as_i64() {
+                        #( Some(#int_values) => Ok(Self::#int_idents), )*
+                        _ => #int_fallback,
+                    },
+                    _ => Err(#krate::JsonError::new(path, #invalid_value)),
+                }
+            }
+
+            #schema
+        }
+
+        impl ::std::str::FromStr for #name {
+            type Err = #krate::ParseEnumError;
+
+            fn from_str(value: &str) -> Result<Self, Self::Err> {
+                match value {
+                    #( #names => Ok(Self::#idents), )*
+                    _ => #parse_fallback,
+                }
+            }
+        }
+
+        impl ::std::convert::TryFrom<&str> for #name {
+            type Error = #krate::ParseEnumError;
+
+            fn try_from(value: &str) -> Result<Self, Self::Error> {
+                value.parse()
+            }
+        }
+    })
+}
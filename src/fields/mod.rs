@@ -0,0 +1,365 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Error, Expr, Fields, Ident, Lit, Type};
+
+use crate::attrs::{ComputedAttrs, ContainerAttrs, FieldAttrs, IndexAttrs, Position};
+use crate::types::{classify, is_emptiable, probe, to_value_with, Render, Scalar, Shape};
+use crate::utils::{
+    apply_rename_all, crate_path, field_contains_rename, get_docs, get_final_name, pointer_token,
+};
+
+/// A struct field together with everything the generators need to know about it.
+pub struct JsonField<'a> {
+    pub ident: &'a Ident,
+    pub ty: &'a Type,
+    pub key: String,
+    pub bson_name: String,
+    pub shape: Shape,
+    pub omit_none: bool,
+    pub omit_empty: bool,
+    pub default_for_none: Option<Lit>,
+    pub reference: bool,
+    pub condition: Option<Expr>,
+    pub since: Option<u32>,
+    pub until: Option<u32>,
+    pub rename_in: Vec<(u32, String)>,
+    pub indexes: Vec<IndexAttrs>,
+    pub docs: String,
+    /// Whether `to_json_with` takes any context `C` rather than the struct's own.
+    pub any_context: bool,
+}
+
+impl<'a> JsonField<'a> {
+    pub fn collect(
+        fields: &'a Fields,
+        rename_all_convention: &Option<String>,
+        container: &ContainerAttrs,
+    ) -> syn::Result<Vec<JsonField<'a>>> {
+        fields
+            .iter()
+            .map(|field| {
+                let ident = field.ident.as_ref().ok_or_else(|| {
+                    Error::new_spanned(field, "ToJson only supports structs with named fields")
+                })?;
+
+                let attrs = FieldAttrs::from_attrs(ident, &field.attrs)?;
+                let bson_name = get_final_name(field, rename_all_convention);
+                // `#[json(rename...)]` only changes the JSON key; serde still decides the stored name.
+                // A serde rename on the field is as explicit, so `json(rename_all)` keeps it.
+                let key = match (attrs.rename, &container.rename_all) {
+                    (Some(rename), _) => rename,
+                    (None, Some(_)) if field_contains_rename(field) => bson_name.clone(),
+                    (None, Some(convention)) => apply_rename_all(convention, &ident.to_string()),
+                    (None, None) => bson_name.clone(),
+                };
+
+                let shape = classify(&field.ty, attrs.nested, attrs.raw);
+                if attrs.raw && shape.leaf() != Scalar::RawJson {
+                    return Err(Error::new_spanned(field, "json(raw) needs a String field"));
+                }
+                let is_option = matches!(shape, Shape::Option(_));
+                if (attrs.omit_none || attrs.default_for_none.is_some()) && !is_option {
+                    return Err(Error::new_spanned(
+                        field,
+                        "omit_none and default_for_none need an Option field",
+                    ));
+                }
+                if attrs.omit_empty && !is_emptiable(&field.ty) {
+                    return Err(Error::new_spanned(
+                        field,
+                        "omit_empty needs a Vec, String, map or set field",
+                    ));
+                }
+
+                // Container policies apply to every field they make sense for.
+                Ok(JsonField {
+                    ident,
+                    ty: &field.ty,
+                    key,
+                    bson_name,
+                    omit_none: is_option
+                        && attrs.default_for_none.is_none()
+                        && (attrs.omit_none || container.omit_none),
+                    omit_empty: is_emptiable(&field.ty)
+                        && (attrs.omit_empty || container.omit_empty),
+                    default_for_none: attrs.default_for_none,
+                    reference: attrs.reference,
+                    condition: attrs.condition,
+                    since: attrs.since,
+                    until: attrs.until,
+                    rename_in: attrs.rename_in,
+                    shape,
+                    indexes: attrs.indexes,
+                    docs: get_docs(&field.attrs),
+                    any_context: container.context.is_none(),
+                })
+            })
+            .collect()
+    }
+
+    // An `Option<Value>` expression for the field of `receiver`, `None` when the
+    // field is left out. Fields without a null or empty policy return `None`.
+    pub fn emit(&self, receiver: TokenStream, mode: Mode) -> Option<TokenStream> {
+        let krate = crate_path();
+        let ident = self.ident;
+        let value = self.value(quote!(&#receiver.#ident), mode);
+
+        let emitted = match &self.default_for_none {
+            Some(default) => quote! {
+                Some(match &#receiver.#ident {
+                    None => #krate::serde_json::json!(#default),
+                    Some(_) => #value,
+                })
+            },
+            None if self.omit_none => quote! {
+                #receiver.#ident.as_ref().map(|_| #value)
+            },
+            None if self.omit_empty => quote! { Some(#value) },
+            None => return None,
+        };
+
+        Some(if self.omit_empty {
+            quote! {
+                (#emitted).filter(|value| !#krate::__private::is_empty_value(value))
+            }
+        } else {
+            emitted
+        })
+    }
+
+    fn value(&self, value: TokenStream, mode: Mode) -> TokenStream {
+        let render = match mode {
+            Mode::Scoped => Render::Scoped,
+            Mode::Context if self.any_context => Render::AnyContext,
+            Mode::Context => Render::Context,
+            Mode::Value | Mode::Stream => Render::Value,
+        };
+        to_value_with(&self.shape, value, render)
+    }
+
+    // The key as written in the version the `render` in scope asks for, if any.
+    fn versioned_key(&self) -> TokenStream {
+        let key = &self.key;
+        if self.rename_in.is_empty() {
+            return quote!(#key);
+        }
+        let (versions, names): (Vec<_>, Vec<_>) = self.rename_in.iter().cloned().unzip();
+        quote! {
+            match render.version() {
+                #( Some(#versions) => #names, )*
+                _ => #key,
+            }
+        }
+    }
+
+    // Whether the field exists in the version the `render` in scope asks for;
+    // without a version every field does.
+    fn version_check(&self) -> Option<TokenStream> {
+        let since = self.since.map(|since| quote!(version >= #since));
+        let until = self.until.map(|until| quote!(version <= #until));
+        let check = match (since, until) {
+            (Some(since), Some(until)) => quote!(#since && #until),
+            (Some(check), None) | (None, Some(check)) => check,
+            (None, None) => return None,
+        };
+        Some(quote! {
+            match render.version() {
+                Some(version) => #check,
+                None => true,
+            }
+        })
+    }
+
+    // The key in API `version`, or `None` when the field is not in it.
+    fn key_in(&self, version: u32) -> Option<&str> {
+        if self.since.is_some_and(|since| version < since)
+            || self.until.is_some_and(|until| version > until)
+        {
+            return None;
+        }
+        let renamed = self.rename_in.iter().find(|(v, _)| *v == version);
+        Some(renamed.map_or(&self.key, |(_, name)| name))
+    }
+
+    // The key as a JSON Pointer reference token.
+    pub fn pointer_token(&self) -> String {
+        pointer_token(&self.key)
+    }
+}
+
+/// How the value of an output key is produced.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// A `serde_json::Value`.
+    Value,
+    /// A `serde_json::Value` as the runtime `render` in scope asks for: cut off
+    /// at a depth, or with the keys of an API version.
+    Scoped,
+    /// A `serde_json::Value` for the `ctx` in scope, leaving out fields it can't see.
+    Context,
+    /// Anything `Serialize`, so plain fields are passed by reference.
+    Stream,
+}
+
+/// One key of the `to_json` output.
+pub enum Entry<'f, 'a> {
+    Field(&'f JsonField<'a>),
+    Computed(&'f ComputedAttrs),
+}
+
+impl Entry<'_, '_> {
+    /// The output keys in order: the fields, with computed keys placed
+    /// before or after the field they name, or at the end.
+    pub fn ordered<'f, 'a>(
+        fields: &'f [JsonField<'a>],
+        computed: &'f [ComputedAttrs],
+    ) -> syn::Result<Vec<Entry<'f, 'a>>> {
+        for computed in computed {
+            if let Position::Before(ident) | Position::After(ident) = &computed.position {
+                if !fields.iter().any(|field| field.ident == ident) {
+                    return Err(Error::new_spanned(
+                        ident,
+                        format!("no field named `{}`", ident),
+                    ));
+                }
+            }
+        }
+        let placed = |is_placed: &dyn Fn(&Position) -> bool| -> Vec<Entry<'f, 'a>> {
+            computed
+                .iter()
+                .filter(|computed| is_placed(&computed.position))
+                .map(Entry::Computed)
+                .collect()
+        };
+
+        let mut entries = vec![];
+        for field in fields {
+            let ident = field.ident;
+            entries.extend(placed(
+                &|position| matches!(position, Position::Before(i) if i == ident),
+            ));
+            entries.push(Entry::Field(field));
+            entries.extend(placed(
+                &|position| matches!(position, Position::After(i) if i == ident),
+            ));
+        }
+        entries.extend(placed(&|position| matches!(position, Position::End)));
+        Ok(entries)
+    }
+
+    /// Rejects two entries writing the same key, in `to_json` or in any API
+    /// version. Versions only differ where a `since`, `until` or `rename_in`
+    /// starts or stops applying, so those are the ones checked.
+    pub fn check_keys(entries: &[Entry], type_tag: Option<&str>) -> syn::Result<()> {
+        let fields = entries.iter().filter_map(|entry| match entry {
+            Entry::Field(field) => Some(*field),
+            Entry::Computed(_) => None,
+        });
+        let mut versions = vec![];
+        for field in fields.clone() {
+            versions.extend(field.since);
+            versions.extend(field.until.map(|until| until.saturating_add(1)));
+            for (version, _) in &field.rename_in {
+                versions.extend([*version, version.saturating_add(1)]);
+            }
+        }
+        versions.sort_unstable();
+        versions.dedup();
+
+        let check = |version: Option<u32>| -> syn::Result<()> {
+            let mut seen: Vec<&str> = type_tag.into_iter().collect();
+            for entry in entries {
+                let (key, span): (&str, &dyn quote::ToTokens) = match entry {
+                    Entry::Computed(computed) => (&computed.name, &computed.with),
+                    Entry::Field(field) => match version {
+                        None => (&field.key, field.ident),
+                        Some(version) => match field.key_in(version) {
+                            Some(key) => (key, field.ident),
+                            None => continue,
+                        },
+                    },
+                };
+                if seen.contains(&key) {
+                    let message = match version {
+                        None => format!("key `{}` is written twice", key),
+                        Some(version) => {
+                            format!("key `{}` is written twice in version {}", key, version)
+                        }
+                    };
+                    return Err(Error::new_spanned(span, message));
+                }
+                seen.push(key);
+            }
+            Ok(())
+        };
+
+        check(None)?;
+        versions
+            .into_iter()
+            .try_for_each(|version| check(Some(version)))
+    }
+
+    // Writes this key out of `self` with `insert(key, value)`.
+    pub fn render(
+        &self,
+        mode: Mode,
+        insert: impl Fn(TokenStream, TokenStream) -> TokenStream,
+    ) -> TokenStream {
+        let krate = crate_path();
+        match self {
+            Entry::Computed(ComputedAttrs { name, with, .. }) => insert(
+                quote!(#name),
+                quote! { #krate::serde_json::Value::from(#with(self)) },
+            ),
+            Entry::Field(field) => {
+                let ident = field.ident;
+                let key = match mode {
+                    Mode::Scoped => field.versioned_key(),
+                    _ => {
+                        let key = &field.key;
+                        quote!(#key)
+                    }
+                };
+                let rendered = match field.emit(quote!(self), mode) {
+                    Some(emitted) => {
+                        let insert = insert(key, quote!(value));
+                        quote! {
+                            if let Some(value) = #emitted {
+                                #insert
+                            }
+                        }
+                    }
+                    None if mode == Mode::Stream && field.shape.leaf().is_serialize() => {
+                        insert(key, quote!(&self.#ident))
+                    }
+                    // Plain fields stream through serde, unless their type derives ToJson.
+                    None if mode == Mode::Stream
+                        && matches!(field.shape, Shape::Scalar(Scalar::Other)) =>
+                    {
+                        insert(
+                            key,
+                            probe(
+                                quote!(Probe::of(&self.#ident)),
+                                quote!(serializable(&self.#ident)),
+                            ),
+                        )
+                    }
+                    None => insert(key, field.value(quote!(&self.#ident), mode)),
+                };
+                let condition = match mode {
+                    Mode::Context => field.condition.as_ref().map(|condition| quote!(#condition)),
+                    Mode::Scoped => field.version_check(),
+                    _ => None,
+                };
+                match condition {
+                    Some(condition) => quote! {
+                        if #condition {
+                            #rendered
+                        }
+                    },
+                    None => rendered,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::Type;
+
+use crate::attrs::ContainerAttrs;
+use crate::fields::JsonField;
+use crate::types::{from_value, leaf_type, option_inner, probe, Scalar, Shape};
+use crate::utils::{crate_path, pointer_token};
+
+// A nested ToJson value, possibly boxed, that a patch can be merged into.
+fn is_nested(shape: &Shape) -> bool {
+    match shape {
+        Shape::Scalar(scalar) => *scalar == Scalar::Nested,
+        Shape::Box(inner) => is_nested(inner),
+        _ => false,
+    }
+}
+
+// A leaf the derive couldn't classify, possibly boxed, as a place to merge into.
+// Returns the dereferenced place and the unboxed shape.
+fn plain_target(shape: &Shape, place: TokenStream) -> Option<(TokenStream, &Shape)> {
+    match shape {
+        Shape::Scalar(Scalar::Other) => Some((place, shape)),
+        Shape::Box(inner) => plain_target(inner, quote!(*#place)),
+        _ => None,
+    }
+}
+
+// Merges into `place` when its type turns out to derive ToJson, otherwise
+// replaces it like any other field. With `check`, only tells whether that works.
+fn merge_plain(
+    shape: &Shape,
+    leaf_ty: &Type,
+    place: TokenStream,
+    check: bool,
+) -> Option<TokenStream> {
+    let (target, unboxed) = plain_target(shape, place)?;
+    let replace = from_value(unboxed, leaf_ty, quote!(value), quote!(&path));
+    let call = if check {
+        quote!(check_merge_patch(&#target, value, &path, || #replace))
+    } else {
+        quote!(merge_patch(&mut #target, value, &path, || #replace))
+    };
+    let merge = probe(quote!(Probe::<#leaf_ty>::new()), call);
+    Some(quote! { #merge?; })
+}
+
+/// Steps into the envelope key of `#value`, moving the `path` in scope along with it.
+pub fn unwrap_envelope(
+    container: &ContainerAttrs,
+    value: TokenStream,
+    missing: TokenStream,
+) -> Option<TokenStream> {
+    let krate = crate_path();
+    let envelope = container.envelope.as_ref()?;
+    let key = &envelope.key;
+    let token = pointer_token(key);
+    let siblings = envelope.siblings.iter().map(|(sibling, _)| sibling);
+
+    Some(quote! {
+        let envelope = #krate::__private::expect_object(#value, path)?;
+        #krate::__private::reject_unknown_keys(envelope, &[#key, #( #siblings ),*], path)?;
+        let path = &format!("{}/{}", path, #token);
+        let #value = match envelope.get(#key) {
+            Some(value) => value,
+            None => #missing,
+        };
+    })
+}
+
+/// Reads the whole of `field` from `value`. `null` empties an `omit_empty`
+/// field, since that is how it is left out of the output.
+pub fn read_field(field: &JsonField, value: TokenStream, path: TokenStream) -> TokenStream {
+    let read = from_value(&field.shape, field.ty, value.clone(), path);
+    match field.shape {
+        Shape::Scalar(_) | Shape::Vec(_) if field.omit_empty => quote! {
+            if (#value).is_null() {
+                Ok(::std::default::Default::default())
+            } else {
+                #read
+            }
+        },
+        _ => read,
+    }
+}
+
+pub fn expand_from_json(fields: &[JsonField], container: &ContainerAttrs) -> TokenStream {
+    let krate = crate_path();
+    let field_values = fields.iter().map(|field| {
+        let ident = field.ident;
+        let key = &field.key;
+        let token = field.pointer_token();
+        let value = from_value(&field.shape, field.ty, quote!(value), quote!(&path));
+        let missing = match field.shape {
+            Shape::Option(_) => quote! { Ok(None) },
+            // omit_empty leaves empty collections out of the output.
+            _ if field.omit_empty => quote! { Ok(::std::default::Default::default()) },
+            _ => quote! { Err(#krate::JsonError::new(&path, "missing field")) },
+        };
+
+        quote! {
+            #ident: {
+                let path = format!("{}/{}", path, #token);
+                match object.get(#key) {
+                    Some(value) => #value,
+                    None => #missing,
+                }?
+            },
+        }
+    });
+
+    let envelope = unwrap_envelope(
+        container,
+        quote!(value),
+        quote! { return Err(#krate::JsonError::new(path, "missing field")) },
+    );
+    // A type tag is optional on input, but has to name this type when present.
+    let type_tag = container.type_tag.as_ref().map(|tag| {
+        let (key, value) = (&tag.key, &tag.value);
+        let token = pointer_token(key);
+        quote! {
+            if let Some(tag) = object.get(#key) {
+                if tag != #value {
+                    return Err(#krate::JsonError::new(
+                        &format!("{}/{}", path, #token),
+                        format!("expected `{}`", #value),
+                    ));
+                }
+            }
+        }
+    });
+
+    quote! {
+        pub fn from_json(value: &#krate::serde_json::Value) -> Result<Self, #krate::JsonError> {
+            Self::__from_json_at(value, "")
+        }
+        #[doc(hidden)]
+        pub fn __from_json_at(value: &#krate::serde_json::Value, path: &str) -> Result<Self, #krate::JsonError> {
+            #envelope
+            let object = #krate::__private::expect_object(value, path)?;
+            #type_tag
+            Ok(Self {
+                #( #field_values )*
+            })
+        }
+    }
+}
+
+pub fn expand_merge_patch(fields: &[JsonField], container: &ContainerAttrs) -> TokenStream {
+    let krate = crate_path();
+    // Computed keys and the type tag are read-only, so they are skipped rather
+    // than rejected: feeding `to_json` output back in must work.
+    let keys = fields
+        .iter()
+        .map(|field| &field.key)
+        .chain(container.computed.iter().map(|computed| &computed.name))
+        .chain(container.type_tag.as_ref().map(|tag| &tag.key))
+        .collect::<Vec<_>>();
+    let envelope = unwrap_envelope(container, quote!(patch), quote! { return Ok(()) });
+
+    // The check runs the same steps on `&self` and keeps nothing, so that a
+    // patch failing halfway through never leaves `self` half patched.
+    let field_patches = |check: bool| {
+        fields.iter().map(move |field| {
+            let ident = field.ident;
+            let key = &field.key;
+            let token = field.pointer_token();
+            let replace = from_value(&field.shape, field.ty, quote!(value), quote!(&path));
+            let leaf_ty = leaf_type(field.ty);
+            let assign = |read: TokenStream| {
+                if check {
+                    quote! { #read?; }
+                } else {
+                    quote! { self.#ident = #read?; }
+                }
+            };
+            let merge_nested = |place: TokenStream| {
+                if check {
+                    quote! { #place.__check_merge_patch_at(value, &path)?; }
+                } else {
+                    quote! { #place.__apply_merge_patch_at(value, &path)?; }
+                }
+            };
+
+            // Nested ToJson values are merged into rather than replaced.
+            let apply = match (&field.shape, option_inner(field.ty)) {
+                (shape, _) if is_nested(shape) => merge_nested(quote!(self.#ident)),
+                (Shape::Scalar(_) | Shape::Vec(_), _) if field.omit_empty => {
+                    assign(read_field(field, quote!(value), quote!(&path)))
+                }
+                (Shape::Option(inner), Some(inner_ty)) => {
+                    let merge = if is_nested(inner) {
+                        Some(merge_nested(quote!(current)))
+                    } else {
+                        merge_plain(inner, leaf_ty, quote!(*current), check)
+                    };
+                    let create = from_value(inner, inner_ty, quote!(value), quote!(&path));
+                    match merge {
+                        Some(merge) if check => quote! {
+                            if !value.is_null() {
+                                if let Some(current) = &self.#ident {
+                                    #merge
+                                } else {
+                                    #create?;
+                                }
+                            }
+                        },
+                        Some(merge) => quote! {
+                            if value.is_null() {
+                                self.#ident = None;
+                            } else if let Some(current) = &mut self.#ident {
+                                #merge
+                            } else {
+                                self.#ident = Some(#create?);
+                            }
+                        },
+                        None => assign(replace),
+                    }
+                }
+                (shape, _) => merge_plain(shape, leaf_ty, quote!(self.#ident), check)
+                    .unwrap_or_else(|| assign(replace)),
+            };
+
+            quote! {
+                if let Some(value) = patch.get(#key) {
+                    let path = format!("{}/{}", path, #token);
+                    #apply
+                }
+            }
+        })
+    };
+    let checks = field_patches(true);
+    let field_patches = field_patches(false);
+
+    quote! {
+        pub fn apply_merge_patch(&mut self, patch: &#krate::serde_json::Value) -> Result<(), #krate::JsonError> {
+            self.__check_merge_patch_at(patch, "")?;
+            self.__apply_merge_patch_at(patch, "")
+        }
+        #[doc(hidden)]
+        pub fn __apply_merge_patch_at(&mut self, patch: &#krate::serde_json::Value, path: &str) -> Result<(), #krate::JsonError> {
+            #envelope
+            let patch = #krate::__private::expect_object(patch, path)?;
+            #krate::__private::reject_unknown_keys(patch, &[#( #keys ),*], path)?;
+
+            #( #field_patches )*
+
+            Ok(())
+        }
+        #[doc(hidden)]
+        pub fn __check_merge_patch_at(&self, patch: &#krate::serde_json::Value, path: &str) -> Result<(), #krate::JsonError> {
+            #envelope
+            let patch = #krate::__private::expect_object(patch, path)?;
+            #krate::__private::reject_unknown_keys(patch, &[#( #keys ),*], path)?;
+
+            #( #checks )*
+
+            Ok(())
+        }
+    }
+}
+
+// Enums have no members to merge, so a patch replaces them.
+pub fn expand_enum_merge() -> TokenStream {
+    let krate = crate_path();
+    quote! {
+        #[doc(hidden)]
+        pub fn __apply_merge_patch_at(&mut self, patch: &#krate::serde_json::Value, path: &str) -> Result<(), #krate::JsonError> {
+            *self = Self::__from_json_at(patch, path)?;
+            Ok(())
+        }
+        #[doc(hidden)]
+        pub fn __check_merge_patch_at(&self, patch: &#krate::serde_json::Value, path: &str) -> Result<(), #krate::JsonError> {
+            Self::__from_json_at(patch, path).map(drop)
+        }
+    }
+}
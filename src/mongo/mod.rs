@@ -0,0 +1,273 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Error, Type};
+
+use crate::attrs::IndexAttrs;
+use crate::fields::JsonField;
+use crate::types::{last_ident, leaf_type, probe, Scalar, Shape};
+use crate::utils::crate_path;
+
+pub fn expand_projection(fields: &[JsonField]) -> TokenStream {
+    let krate = crate_path();
+    let bson_names = fields.iter().map(|field| &field.bson_name);
+
+    quote! {
+        pub fn mongo_projection() -> #krate::mongodb::bson::Document {
+            let mut projection = #krate::mongodb::bson::Document::new();
+            #( projection.insert(#bson_names, 1); )*
+            projection
+        }
+        pub fn mongo_projection_for(json_paths: &[&str]) -> Result<#krate::mongodb::bson::Document, #krate::JsonError> {
+            let mut projection = #krate::mongodb::bson::Document::new();
+            for json_path in json_paths {
+                let bson_path = Self::__bson_path(json_path)
+                    .ok_or_else(|| #krate::JsonError::new("", format!("unknown field `{}`", json_path)))?;
+                projection.insert(bson_path, 1);
+            }
+            Ok(projection)
+        }
+    }
+}
+
+// Nested types translate the rest of a path whether or not they opt into `mongo`.
+pub fn expand_bson_path(fields: &[JsonField]) -> TokenStream {
+    let path_arms = fields.iter().map(|field| {
+        let key = &field.key;
+        let bson_name = &field.bson_name;
+
+        let leaf_ty = leaf_type(field.ty);
+        let nested = if field.shape.leaf() == Scalar::Nested {
+            quote! {
+                Some(rest) => <#leaf_ty>::__bson_path(rest).map(|rest| format!("{}.{}", #bson_name, rest)),
+            }
+        } else if field.shape.leaf() == Scalar::Other {
+            let bson_path = probe(quote!(Probe::<#leaf_ty>::new()), quote!(bson_path(rest)));
+            quote! {
+                Some(rest) => #bson_path.map(|rest| format!("{}.{}", #bson_name, rest)),
+            }
+        } else {
+            quote! {
+                Some(_) => None,
+            }
+        };
+
+        quote! {
+            #key => match rest {
+                None => Some(#bson_name.to_string()),
+                #nested
+            },
+        }
+    });
+
+    quote! {
+        // Translates a dot-separated path of JSON keys into the stored field path.
+        #[doc(hidden)]
+        pub fn __bson_path(json_path: &str) -> Option<String> {
+            let (head, rest) = match json_path.split_once('.') {
+                Some((head, rest)) => (head, Some(rest)),
+                None => (json_path, None),
+            };
+            match head {
+                #( #path_arms )*
+                _ => None,
+            }
+        }
+    }
+}
+
+pub fn expand_enum_projection() -> TokenStream {
+    quote! {
+        #[doc(hidden)]
+        pub fn __bson_path(_json_path: &str) -> Option<String> {
+            None
+        }
+    }
+}
+
+// The `bsonType` a leaf is stored as, when the derive can tell.
+fn bson_type(scalar: Scalar, leaf_ty: &Type) -> Option<&'static str> {
+    match scalar {
+        Scalar::ObjectId => Some("objectId"),
+        Scalar::DateTime => Some("date"),
+        Scalar::RawJson => Some("string"),
+        Scalar::Other => match last_ident(leaf_ty)?.to_string().as_str() {
+            "String" | "str" | "char" => Some("string"),
+            "bool" => Some("bool"),
+            "i8" | "i16" | "i32" | "u8" | "u16" => Some("int"),
+            "i64" | "u32" | "u64" | "isize" | "usize" => Some("long"),
+            "f32" | "f64" => Some("double"),
+            "HashMap" | "BTreeMap" | "IndexMap" => Some("object"),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn schema(shape: &Shape, leaf_ty: &Type) -> TokenStream {
+    let krate = crate_path();
+    match shape {
+        Shape::Scalar(Scalar::Nested) => quote! {
+            <#leaf_ty>::__bson_schema()
+        },
+        Shape::Scalar(scalar) => {
+            let schema = match bson_type(*scalar, leaf_ty) {
+                Some(bson_type) => quote! {
+                    #krate::mongodb::bson::doc! { "bsonType": #bson_type }
+                },
+                None => quote! {
+                    #krate::mongodb::bson::Document::new()
+                },
+            };
+            if *scalar == Scalar::Other {
+                probe(
+                    quote!(Probe::<#leaf_ty>::new()),
+                    quote!(bson_schema(#schema)),
+                )
+            } else {
+                schema
+            }
+        }
+        Shape::Box(inner) => schema(inner, leaf_ty),
+        Shape::Option(inner) => {
+            let inner = schema(inner, leaf_ty);
+            quote! {
+                #krate::__private::nullable_schema(#inner)
+            }
+        }
+        Shape::Vec(inner) => {
+            let inner = schema(inner, leaf_ty);
+            quote! {
+                {
+                    let items = #inner;
+                    #krate::mongodb::bson::doc! { "bsonType": "array", "items": items }
+                }
+            }
+        }
+    }
+}
+
+pub fn expand_validator() -> TokenStream {
+    let krate = crate_path();
+    quote! {
+        pub fn mongo_validator() -> #krate::mongodb::bson::Document {
+            #krate::mongodb::bson::doc! { "$jsonSchema": Self::__bson_schema() }
+        }
+    }
+}
+
+pub fn expand_bson_schema(fields: &[JsonField]) -> TokenStream {
+    let krate = crate_path();
+    let properties = fields.iter().map(|field| {
+        let bson_name = &field.bson_name;
+        let schema = schema(&field.shape, leaf_type(field.ty));
+
+        quote! {
+            properties.insert(#bson_name, #schema);
+        }
+    });
+
+    let required: Vec<&String> = fields
+        .iter()
+        .filter(|field| !matches!(field.shape, Shape::Option(_)))
+        .map(|field| &field.bson_name)
+        .collect();
+    let required = (!required.is_empty()).then(|| {
+        quote! {
+            schema.insert("required", vec![#( #required ),*]);
+        }
+    });
+
+    quote! {
+        #[doc(hidden)]
+        pub fn __bson_schema() -> #krate::mongodb::bson::Document {
+            #krate::__private::recursive_schema::<Self>(|| {
+                let mut properties = #krate::mongodb::bson::Document::new();
+                #( #properties )*
+
+                let mut schema = #krate::mongodb::bson::doc! { "bsonType": "object" };
+                #required
+                schema.insert("properties", properties);
+                schema
+            })
+        }
+    }
+}
+
+// Unit enums list their values; enums carrying data are left unconstrained.
+pub fn expand_enum_schema(values: Option<&[TokenStream]>) -> TokenStream {
+    let krate = crate_path();
+    let schema = match values {
+        Some(values) => quote! {
+            #krate::mongodb::bson::doc! { "enum": [#( #values ),*] }
+        },
+        None => quote! {
+            #krate::mongodb::bson::Document::new()
+        },
+    };
+
+    quote! {
+        #[doc(hidden)]
+        pub fn __bson_schema() -> #krate::mongodb::bson::Document {
+            #schema
+        }
+    }
+}
+
+fn index_model(index: &IndexAttrs, fields: &[JsonField]) -> syn::Result<TokenStream> {
+    let krate = crate_path();
+    // MongoDB only expires documents through single-field indexes.
+    if let (Some(_), Some((ident, _))) = (index.ttl, index.keys.get(1)) {
+        return Err(Error::new_spanned(
+            ident,
+            "ttl needs an index on a single field, MongoDB ignores it on compound indexes",
+        ));
+    }
+    let mut keys = vec![];
+    for (ident, order) in &index.keys {
+        let field = fields
+            .iter()
+            .find(|field| field.ident == ident)
+            .ok_or_else(|| Error::new_spanned(ident, format!("no field named `{}`", ident)))?;
+        // MongoDB refuses the `unique` option on its own `_id` index.
+        let bson_name = &field.bson_name;
+        if index.unique && index.keys.len() == 1 && bson_name == "_id" {
+            return Err(Error::new_spanned(
+                ident,
+                "`_id` is always unique, MongoDB rejects unique on its index",
+            ));
+        }
+        keys.push(quote! { #bson_name: #order });
+    }
+
+    let unique = index.unique.then(|| quote! { .unique(true) });
+    let ttl = index.ttl.map(|ttl| {
+        quote! { .expire_after(::std::time::Duration::from_secs(#ttl)) }
+    });
+    let name = index
+        .name
+        .as_ref()
+        .map(|name| quote! { .name(#name.to_string()) });
+
+    Ok(quote! {
+        #krate::mongodb::IndexModel::builder()
+            .keys(#krate::mongodb::bson::doc! { #( #keys ),* })
+            .options(#krate::mongodb::options::IndexOptions::builder() #unique #ttl #name .build())
+            .build()
+    })
+}
+
+// Container-level compound indexes first, then the ones declared on fields.
+pub fn expand_indexes(indexes: &[IndexAttrs], fields: &[JsonField]) -> syn::Result<TokenStream> {
+    let krate = crate_path();
+    let models = indexes
+        .iter()
+        .chain(fields.iter().flat_map(|field| &field.indexes))
+        .map(|index| index_model(index, fields))
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    Ok(quote! {
+        pub fn index_models() -> Vec<#krate::mongodb::IndexModel> {
+            vec![#( #models ),*]
+        }
+    })
+}
@@ -0,0 +1,65 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Ident, Type};
+
+use crate::utils::crate_path;
+
+// Lets fields holding this type find its ToJson impl without `#[json]`.
+// `value` renders `self` as the `render` in scope says, and `bounds` is the
+// where clause of a `to_json_with` generic over its context.
+pub fn expand_node(
+    name: &Ident,
+    value: TokenStream,
+    context: Option<&Type>,
+    bounds: Option<&TokenStream>,
+) -> TokenStream {
+    let krate = crate_path();
+    let with_context = match context {
+        Some(context) => quote! {
+            impl #krate::__private::JsonNodeWith<#context> for #name {
+                fn json_value_with(&self, ctx: &#context) -> #krate::serde_json::Value {
+                    self.to_json_with(ctx)
+                }
+            }
+        },
+        None => quote! {
+            impl<C: #krate::JsonContext + ?Sized> #krate::__private::JsonNodeWith<C> for #name #bounds {
+                fn json_value_with(&self, ctx: &C) -> #krate::serde_json::Value {
+                    self.to_json_with(ctx)
+                }
+            }
+        },
+    };
+
+    quote! {
+        impl #krate::__private::JsonNode for #name {
+            #[allow(unused_variables)]
+            fn json_value(&self, render: #krate::__private::Render) -> #krate::serde_json::Value {
+                #value
+            }
+            fn json_reference(&self) -> #krate::serde_json::Value {
+                self.__json_reference()
+            }
+            fn json_diff_into(&self, other: &Self, path: &str, changes: &mut Vec<#krate::Change>) {
+                self.__json_diff_into(other, path, changes)
+            }
+            fn from_json_at(value: &#krate::serde_json::Value, path: &str) -> Result<Self, #krate::JsonError> {
+                Self::__from_json_at(value, path)
+            }
+            fn apply_merge_patch_at(&mut self, patch: &#krate::serde_json::Value, path: &str) -> Result<(), #krate::JsonError> {
+                self.__apply_merge_patch_at(patch, path)
+            }
+            fn check_merge_patch_at(&self, patch: &#krate::serde_json::Value, path: &str) -> Result<(), #krate::JsonError> {
+                self.__check_merge_patch_at(patch, path)
+            }
+            fn bson_path(json_path: &str) -> Option<String> {
+                Self::__bson_path(json_path)
+            }
+            fn bson_schema() -> #krate::mongodb::bson::Document {
+                Self::__bson_schema()
+            }
+        }
+
+        #with_context
+    }
+}
@@ -0,0 +1,150 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Ident, Visibility};
+
+use crate::attrs::ContainerAttrs;
+use crate::fields::JsonField;
+use crate::merge::{read_field, unwrap_envelope};
+use crate::types::{from_value, option_inner, Shape};
+use crate::utils::crate_path;
+
+pub fn expand_patch(
+    name: &Ident,
+    vis: &Visibility,
+    fields: &[JsonField],
+    container: &ContainerAttrs,
+) -> TokenStream {
+    let krate = crate_path();
+    let patch_name = format_ident!("{}Patch", name);
+    // Read-only keys of the output are let through and ignored.
+    let keys = fields
+        .iter()
+        .map(|field| &field.key)
+        .chain(container.computed.iter().map(|computed| &computed.name))
+        .chain(container.type_tag.as_ref().map(|tag| &tag.key));
+
+    // Without the envelope key there is nothing to patch, as with `apply_merge_patch`.
+    let envelope = unwrap_envelope(
+        container,
+        quote!(value),
+        quote! { return Ok(Self::default()) },
+    );
+
+    let mut patch_fields = vec![];
+    let mut field_inits = vec![];
+    let mut applies = vec![];
+    let mut set_entries = vec![];
+
+    for field in fields {
+        let ident = field.ident;
+        let key = &field.key;
+        let bson_name = &field.bson_name;
+        let token = field.pointer_token();
+
+        // `Option<T>` fields patch with a `T`, and `null` clears them.
+        let (value_ty, value_shape, nullable) = match (&field.shape, option_inner(field.ty)) {
+            (Shape::Option(inner), Some(inner_ty)) => (inner_ty, &**inner, true),
+            _ => (field.ty, &field.shape, false),
+        };
+        // Read like `from_json` reads the field, so both accept the same input.
+        let value = if nullable {
+            from_value(value_shape, value_ty, quote!(value), quote!(&path))
+        } else {
+            read_field(field, quote!(value), quote!(&path))
+        };
+
+        patch_fields.push(quote! {
+            pub #ident: #krate::PatchField<#value_ty>,
+        });
+
+        // `read_field` already takes `null` as empty for omit_empty fields.
+        let null = if nullable {
+            Some(quote! { Some(value) if value.is_null() => #krate::PatchField::Null, })
+        } else if field.omit_empty {
+            None
+        } else {
+            Some(quote! {
+                Some(value) if value.is_null() => return Err(#krate::JsonError::new(&path, "cannot be null")),
+            })
+        };
+        field_inits.push(quote! {
+            #ident: {
+                let path = format!("{}/{}", path, #token);
+                match object.get(#key) {
+                    None => #krate::PatchField::Absent,
+                    #null
+                    Some(value) => #krate::PatchField::Value(#value?),
+                }
+            },
+        });
+
+        applies.push(if nullable {
+            quote! {
+                match self.#ident {
+                    #krate::PatchField::Absent => {}
+                    #krate::PatchField::Null => target.#ident = None,
+                    #krate::PatchField::Value(value) => target.#ident = Some(value),
+                }
+            }
+        } else {
+            quote! {
+                if let #krate::PatchField::Value(value) = self.#ident {
+                    target.#ident = value;
+                }
+            }
+        });
+
+        let unset = if nullable {
+            quote! { unset.insert(#bson_name, ""); }
+        } else {
+            quote! {}
+        };
+        set_entries.push(quote! {
+            match &self.#ident {
+                #krate::PatchField::Absent => {}
+                #krate::PatchField::Null => { #unset }
+                #krate::PatchField::Value(value) => {
+                    set.insert(#bson_name, #krate::mongodb::bson::to_bson(value).expect("Failed to serialize to BSON"));
+                }
+            }
+        });
+    }
+
+    quote! {
+        #[derive(Default)]
+        #vis struct #patch_name {
+            #( #patch_fields )*
+        }
+
+        impl #patch_name {
+            pub fn from_json(value: &#krate::serde_json::Value) -> Result<Self, #krate::JsonError> {
+                let path = "";
+                #envelope
+                let object = #krate::__private::expect_object(value, path)?;
+                #krate::__private::reject_unknown_keys(object, &[#( #keys ),*], path)?;
+
+                Ok(Self {
+                    #( #field_inits )*
+                })
+            }
+            pub fn apply_to(self, target: &mut #name) {
+                #( #applies )*
+            }
+            pub fn to_set_document(&self) -> #krate::mongodb::bson::Document {
+                let mut set = #krate::mongodb::bson::Document::new();
+                let mut unset = #krate::mongodb::bson::Document::new();
+
+                #( #set_entries )*
+
+                let mut update = #krate::mongodb::bson::Document::new();
+                if !set.is_empty() {
+                    update.insert("$set", set);
+                }
+                if !unset.is_empty() {
+                    update.insert("$unset", unset);
+                }
+                update
+            }
+        }
+    }
+}
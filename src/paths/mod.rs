@@ -0,0 +1,337 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{GenericArgument, Ident, PathArguments, Type, Visibility};
+
+use crate::fields::JsonField;
+use crate::types::{leaf_type, option_inner, vec_element, Scalar, Shape};
+use crate::utils::crate_path;
+
+pub fn expand_field_constants(fields: &[JsonField]) -> TokenStream {
+    let constants = fields.iter().map(|field| {
+        let ident = field.ident.to_string();
+        let constant = format_ident!(
+            "FIELD_{}",
+            ident.trim_start_matches("r#").to_ascii_uppercase()
+        );
+        let key = &field.key;
+
+        quote! {
+            pub const #constant: &'static str = #key;
+        }
+    });
+
+    quote! {
+        #( #constants )*
+    }
+}
+
+// The type as written, as in `Option<Box<Category>>`, `[u8; 4]` or `&'a str`.
+// Rarer types keep the spacing `quote` gives their tokens.
+fn type_name(ty: &Type) -> String {
+    let join = |names: Vec<String>| names.join(", ");
+    match ty {
+        Type::Path(type_path) if type_path.qself.is_none() => {
+            let segments = type_path
+                .path
+                .segments
+                .iter()
+                .map(|segment| match &segment.arguments {
+                    PathArguments::AngleBracketed(arguments) => {
+                        let arguments = arguments.args.iter().map(|argument| match argument {
+                            GenericArgument::Type(ty) => type_name(ty),
+                            other => quote!(#other).to_string(),
+                        });
+                        format!("{}<{}>", segment.ident, join(arguments.collect()))
+                    }
+                    _ => quote!(#segment).to_string(),
+                });
+            segments.collect::<Vec<_>>().join("::")
+        }
+        Type::Reference(reference) => {
+            let lifetime = reference.lifetime.as_ref();
+            let lifetime = lifetime.map(|lifetime| format!("{} ", lifetime));
+            let mutability = reference.mutability.map(|_| "mut ");
+            format!(
+                "&{}{}{}",
+                lifetime.unwrap_or_default(),
+                mutability.unwrap_or_default(),
+                type_name(&reference.elem)
+            )
+        }
+        Type::Slice(slice) => format!("[{}]", type_name(&slice.elem)),
+        Type::Array(array) => {
+            let len = &array.len;
+            format!("[{}; {}]", type_name(&array.elem), quote!(#len))
+        }
+        Type::Tuple(tuple) => {
+            let comma = if tuple.elems.len() == 1 { "," } else { "" };
+            format!(
+                "({}{})",
+                join(tuple.elems.iter().map(type_name).collect()),
+                comma
+            )
+        }
+        other => quote!(#other).to_string(),
+    }
+}
+
+pub fn expand_field_info(name: &Ident, fields: &[JsonField]) -> TokenStream {
+    let krate = crate_path();
+    let infos = fields.iter().map(|field| {
+        let name = field.ident.to_string();
+        let name = name.trim_start_matches("r#");
+        let key = &field.key;
+        let bson_name = &field.bson_name;
+        let type_name = type_name(field.ty);
+        let is_option = matches!(field.shape, Shape::Option(_));
+        let is_vec = match &field.shape {
+            Shape::Option(inner) => matches!(**inner, Shape::Vec(_)),
+            shape => matches!(shape, Shape::Vec(_)),
+        };
+        // Unmarked fields count when their type derives ToJson all the same.
+        let nested = match field.shape.leaf() {
+            Scalar::Nested => quote!(true),
+            Scalar::Other => {
+                let leaf_ty = leaf_type(field.ty);
+                quote!(#krate::__private::Probe::<#leaf_ty>::IS_NODE)
+            }
+            _ => quote!(false),
+        };
+        let docs = &field.docs;
+
+        quote! {
+            #krate::FieldInfo {
+                name: #name,
+                key: #key,
+                bson_name: #bson_name,
+                type_name: #type_name,
+                is_option: #is_option,
+                is_vec: #is_vec,
+                nested: #nested,
+                docs: #docs,
+            }
+        }
+    });
+
+    quote! {
+        impl #krate::ToJsonFields for #name {
+            fn fields() -> &'static [#krate::FieldInfo] {
+                #[allow(unused_imports)]
+                use #krate::__private::ProbeConsts as _;
+                &[#( #infos ),*]
+            }
+        }
+    }
+}
+
+// A generated `<Name><suffix>` struct with one method per field, handing out
+// `leaf` values for plain fields and the nested type's own selector otherwise.
+struct Selector {
+    suffix: &'static str,
+    trait_path: TokenStream,
+    assoc: Ident,
+    with_prefix: Ident,
+    entry: Ident,
+}
+
+fn expand_selector(
+    name: &Ident,
+    vis: &Visibility,
+    fields: &[JsonField],
+    selector: Selector,
+    leaf: impl Fn(&JsonField) -> (TokenStream, TokenStream),
+) -> TokenStream {
+    let krate = crate_path();
+    let Selector {
+        suffix,
+        trait_path,
+        assoc,
+        with_prefix,
+        entry,
+    } = selector;
+    let selector_name = format_ident!("{}{}", name, suffix);
+
+    let methods = fields.iter().map(|field| {
+        let ident = field.ident;
+        let bson_name = &field.bson_name;
+
+        if field.shape.leaf() == Scalar::Nested {
+            let leaf_ty = leaf_type(field.ty);
+            quote! {
+                pub fn #ident(&self) -> <#leaf_ty as #trait_path>::#assoc {
+                    <#leaf_ty as #trait_path>::#with_prefix(
+                        #krate::FieldPath::join(&self.prefix, #bson_name).as_str(),
+                    )
+                }
+            }
+        } else {
+            let (leaf_ty, leaf_value) = leaf(field);
+            quote! {
+                pub fn #ident(&self) -> #leaf_ty {
+                    let path = #krate::FieldPath::join(&self.prefix, #bson_name);
+                    #leaf_value
+                }
+            }
+        }
+    });
+
+    quote! {
+        #vis struct #selector_name {
+            prefix: String,
+        }
+
+        impl #selector_name {
+            #( #methods )*
+        }
+
+        impl ::std::fmt::Display for #selector_name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                f.write_str(&self.prefix)
+            }
+        }
+
+        impl #trait_path for #name {
+            type #assoc = #selector_name;
+
+            fn #with_prefix(prefix: &str) -> Self::#assoc {
+                #selector_name {
+                    prefix: prefix.to_string(),
+                }
+            }
+        }
+
+        impl #name {
+            pub fn #entry() -> #selector_name {
+                <Self as #trait_path>::#with_prefix("")
+            }
+        }
+    }
+}
+
+// The type a filter compares a field against, without its `Option`.
+fn value_type(ty: &Type) -> &Type {
+    option_inner(ty).unwrap_or(ty)
+}
+
+pub fn expand_paths(name: &Ident, vis: &Visibility, fields: &[JsonField]) -> TokenStream {
+    let krate = crate_path();
+    let paths = Selector {
+        suffix: "Paths",
+        trait_path: quote!(#krate::ToJsonPaths),
+        assoc: format_ident!("Paths"),
+        with_prefix: format_ident!("paths_with_prefix"),
+        entry: format_ident!("paths"),
+    };
+
+    let paths = expand_selector(name, vis, fields, paths, |_| {
+        (quote!(#krate::FieldPath), quote!(path))
+    });
+    let checks = expand_selector_checks(fields);
+
+    quote! {
+        #paths
+        #checks
+
+        impl #krate::__private::Selectable for #name {}
+    }
+}
+
+// Selectors only reach into `#[json]` fields, so one left unmarked while its type
+// has selectors of its own would silently hand out a plain path instead.
+fn expand_selector_checks(fields: &[JsonField]) -> TokenStream {
+    let krate = crate_path();
+    let checks = fields
+        .iter()
+        .filter(|field| field.shape.leaf() == Scalar::Other)
+        .map(|field| {
+            let leaf_ty = leaf_type(field.ty);
+            let message = format!(
+                "field `{}` holds a type with selectors of its own, mark it #[json]",
+                field.ident.to_string().trim_start_matches("r#")
+            );
+            quote! {
+                assert!(!#krate::__private::Probe::<#leaf_ty>::HAS_SELECTORS, #message);
+            }
+        });
+
+    quote! {
+        const _: () = {
+            #[allow(unused_imports)]
+            use #krate::__private::ProbeConsts as _;
+            #( #checks )*
+        };
+    }
+}
+
+pub fn expand_filter_and_sort(name: &Ident, vis: &Visibility, fields: &[JsonField]) -> TokenStream {
+    let krate = crate_path();
+    let filter = Selector {
+        suffix: "Filter",
+        trait_path: quote!(#krate::ToJsonFilter),
+        assoc: format_ident!("Filter"),
+        with_prefix: format_ident!("filter_with_prefix"),
+        entry: format_ident!("filter"),
+    };
+    let sort = Selector {
+        suffix: "Sort",
+        trait_path: quote!(#krate::ToJsonSort),
+        assoc: format_ident!("Sort"),
+        with_prefix: format_ident!("sort_with_prefix"),
+        entry: format_ident!("sort"),
+    };
+
+    let filter = expand_selector(name, vis, fields, filter, |field| {
+        let ty = value_type(field.ty);
+        match vec_element(ty) {
+            Some(element_ty) => (
+                quote!(#krate::ArrayFilter<#element_ty>),
+                quote!(#krate::ArrayFilter::new(path)),
+            ),
+            None => (
+                quote!(#krate::FieldFilter<#ty>),
+                quote!(#krate::FieldFilter::new(path)),
+            ),
+        }
+    });
+    let sort = expand_selector(name, vis, fields, sort, |_| {
+        (
+            quote!(#krate::SortField),
+            quote!(#krate::SortField::new(path)),
+        )
+    });
+
+    quote! {
+        #filter
+        #sort
+    }
+}
+
+// An enum is stored as a single value, so its selectors end where it sits.
+pub fn expand_enum_paths(name: &Ident) -> TokenStream {
+    let krate = crate_path();
+    quote! {
+        impl #krate::ToJsonPaths for #name {
+            type Paths = #krate::FieldPath;
+
+            fn paths_with_prefix(prefix: &str) -> Self::Paths {
+                #krate::FieldPath::new(prefix)
+            }
+        }
+
+        impl #krate::ToJsonFilter for #name {
+            type Filter = #krate::FieldFilter<Self>;
+
+            fn filter_with_prefix(prefix: &str) -> Self::Filter {
+                #krate::FieldFilter::new(#krate::FieldPath::new(prefix))
+            }
+        }
+
+        impl #krate::ToJsonSort for #name {
+            type Sort = #krate::SortField;
+
+            fn sort_with_prefix(prefix: &str) -> Self::Sort {
+                #krate::SortField::new(#krate::FieldPath::new(prefix))
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Ident, Type};
+
+use crate::attrs::ContainerAttrs;
+use crate::fields::{JsonField, Mode};
+use crate::merge::read_field;
+use crate::types::{from_value, to_value, wrapped_type, Scalar, Shape};
+use crate::utils::crate_path;
+
+// An `Option<Value>` expression for the JSON Pointer `rest` (empty or starting
+// with `/`) inside `value`, a reference to a value of type `shape`.
+fn get_shape(shape: &Shape, value: TokenStream) -> TokenStream {
+    let krate = crate_path();
+    let whole = to_value(shape, value.clone());
+    let walk = match shape {
+        Shape::Scalar(Scalar::Nested) => quote! {
+            #krate::JsonPointer::json_get(#value, rest)
+        },
+        Shape::Box(inner) => get_shape(inner, quote!(&**#value)),
+        Shape::Option(inner) => {
+            let inner = get_shape(inner, quote!(value));
+            quote! {
+                match #value {
+                    Some(value) => #inner,
+                    None => None,
+                }
+            }
+        }
+        Shape::Vec(inner) => {
+            let inner = get_shape(inner, quote!(value));
+            quote! {
+                #krate::__private::split_pointer(rest).and_then(|(token, rest)| {
+                    let value = #krate::__private::array_index(&token).and_then(|index| (#value).get(index))?;
+                    #inner
+                })
+            }
+        }
+        // Anything else is converted on its own and looked into.
+        Shape::Scalar(_) => quote! {
+            (#whole).pointer(rest).cloned()
+        },
+    };
+
+    quote! {
+        if rest.is_empty() {
+            Some(#whole)
+        } else {
+            #walk
+        }
+    }
+}
+
+// Sets the JSON Pointer `rest` inside `place` (a `&mut` to a value of type
+// `ty`) to `value`, as a `Result<(), JsonError>` expression.
+fn set_shape(shape: &Shape, ty: &Type) -> TokenStream {
+    let replace = from_value(shape, ty, quote!(&value), quote!(&path));
+    let walk = set_inside(shape, ty);
+
+    quote! {
+        if rest.is_empty() {
+            *place = #replace?;
+            Ok(())
+        } else {
+            #walk
+        }
+    }
+}
+
+// Like `set_shape`, for a `rest` that is not empty.
+fn set_inside(shape: &Shape, ty: &Type) -> TokenStream {
+    let krate = crate_path();
+    let replace = from_value(shape, ty, quote!(&value), quote!(&path));
+    let inner_ty = wrapped_type(ty).unwrap_or(ty);
+    match shape {
+        Shape::Scalar(Scalar::Nested) => quote! {
+            place.__json_set_at(rest, value, &path)
+        },
+        Shape::Box(inner) => {
+            let inner = set_shape(inner, inner_ty);
+            quote! {
+                {
+                    let place = &mut **place;
+                    #inner
+                }
+            }
+        }
+        Shape::Option(inner) => {
+            let inner = set_shape(inner, inner_ty);
+            quote! {
+                match place {
+                    Some(place) => #inner,
+                    None => Err(#krate::JsonError::new(&path, "no value to set into")),
+                }
+            }
+        }
+        Shape::Vec(inner) => {
+            let inner = set_shape(inner, inner_ty);
+            quote! {
+                {
+                    let (token, rest) = #krate::__private::split_pointer(rest)
+                        .ok_or_else(|| #krate::JsonError::new(&path, "expected a JSON Pointer"))?;
+                    let path = format!("{}/{}", path, token);
+                    let place = #krate::__private::array_index(&token)
+                        .and_then(|index| place.get_mut(index))
+                        .ok_or_else(|| #krate::JsonError::new(&path, "no such index"))?;
+                    #inner
+                }
+            }
+        }
+        // Anything else is converted on its own, changed and read back.
+        Shape::Scalar(_) => {
+            let current = to_value(shape, quote!(&*place));
+            quote! {
+                {
+                    let mut current = #current;
+                    if !#krate::__private::pointer_set(&mut current, rest, value) {
+                        return Err(#krate::JsonError::new(&format!("{}{}", path, rest), "no such key"));
+                    }
+                    let value = current;
+                    *place = #replace?;
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+pub fn expand_pointer(
+    name: &Ident,
+    fields: &[JsonField],
+    container: &ContainerAttrs,
+) -> TokenStream {
+    let krate = crate_path();
+
+    let get_arms = fields.iter().map(|field| {
+        let ident = field.ident;
+        let key = &field.key;
+        let get = get_shape(&field.shape, quote!(&self.#ident));
+        // Keys left out of the output are left out here too.
+        let get = match field.emit(quote!(self), Mode::Value) {
+            Some(emitted) => quote! {
+                if rest.is_empty() {
+                    #emitted
+                } else {
+                    #get
+                }
+            },
+            None => get,
+        };
+        quote! {
+            #key => #get,
+        }
+    });
+    // A whole field is read the way a merge patch reads it.
+    let set_arms = fields.iter().map(|field| {
+        let ident = field.ident;
+        let key = &field.key;
+        let read = read_field(field, quote!(&value), quote!(&path));
+        let set = set_inside(&field.shape, field.ty);
+        quote! {
+            #key => {
+                let place = &mut self.#ident;
+                if rest.is_empty() {
+                    *place = #read?;
+                    Ok(())
+                } else {
+                    #set
+                }
+            }
+        }
+    });
+
+    let computed_gets = container.computed.iter().map(|computed| {
+        let (name, with) = (&computed.name, &computed.with);
+        quote! {
+            #name => #krate::serde_json::Value::from(#with(self)).pointer(rest).cloned(),
+        }
+    });
+    let read_only: Vec<&String> = container
+        .computed
+        .iter()
+        .map(|computed| &computed.name)
+        .chain(container.type_tag.as_ref().map(|tag| &tag.key))
+        .collect();
+    let read_only = (!read_only.is_empty()).then(|| {
+        quote! {
+            #( #read_only )|* => Err(#krate::JsonError::new(&path, "key cannot be set")),
+        }
+    });
+    let type_tag_get = container.type_tag.as_ref().map(|tag| {
+        let (key, value) = (&tag.key, &tag.value);
+        quote! {
+            #key if rest.is_empty() => Some(#krate::serde_json::Value::String(#value.to_string())),
+        }
+    });
+
+    // Pointers start at the envelope key; anything else in the envelope comes
+    // from the full output.
+    let envelope_get = container.envelope.as_ref().map(|envelope| {
+        let key = &envelope.key;
+        quote! {
+            let pointer = match #krate::__private::split_pointer(pointer) {
+                Some((token, rest)) if token == #key && !rest.is_empty() => rest,
+                _ => return self.to_json().pointer(pointer).cloned(),
+            };
+        }
+    });
+    let envelope_set = container.envelope.as_ref().map(|envelope| {
+        let key = &envelope.key;
+        quote! {
+            let (pointer, path) = match #krate::__private::split_pointer(pointer) {
+                Some((token, rest)) if token == #key && !rest.is_empty() => {
+                    (rest, format!("{}{}", path, &pointer[..pointer.len() - rest.len()]))
+                }
+                _ => return Err(#krate::JsonError::new(&format!("{}{}", path, pointer), "only keys inside the envelope can be set")),
+            };
+            let path = path.as_str();
+        }
+    });
+
+    quote! {
+        impl #name {
+            #[doc(hidden)]
+            #[allow(unused_variables)]
+            pub fn __json_set_at(&mut self, pointer: &str, value: #krate::serde_json::Value, path: &str) -> Result<(), #krate::JsonError> {
+                if pointer.is_empty() {
+                    *self = Self::__from_json_at(&value, path)?;
+                    return Ok(());
+                }
+                #envelope_set
+                let (token, rest) = #krate::__private::split_pointer(pointer)
+                    .ok_or_else(|| #krate::JsonError::new(path, "expected a JSON Pointer"))?;
+                let path = format!("{}{}", path, &pointer[..pointer.len() - rest.len()]);
+                match token.as_str() {
+                    #( #set_arms )*
+                    #read_only
+                    _ => Err(#krate::JsonError::new(&path, "unknown field")),
+                }
+            }
+        }
+
+        impl #krate::JsonPointer for #name {
+            #[allow(unused_variables)]
+            fn json_get(&self, pointer: &str) -> Option<#krate::serde_json::Value> {
+                if pointer.is_empty() {
+                    return Some(self.to_json());
+                }
+                #envelope_get
+                let (token, rest) = #krate::__private::split_pointer(pointer)?;
+                match token.as_str() {
+                    #( #get_arms )*
+                    #type_tag_get
+                    #( #computed_gets )*
+                    _ => None,
+                }
+            }
+            fn json_set(&mut self, pointer: &str, value: #krate::serde_json::Value) -> Result<(), #krate::JsonError> {
+                self.__json_set_at(pointer, value, "")
+            }
+        }
+    }
+}
+
+// Enums are read and replaced as a whole.
+pub fn expand_enum_pointer(name: &Ident) -> TokenStream {
+    let krate = crate_path();
+    quote! {
+        impl #name {
+            #[doc(hidden)]
+            pub fn __json_set_at(&mut self, pointer: &str, value: #krate::serde_json::Value, path: &str) -> Result<(), #krate::JsonError> {
+                if !pointer.is_empty() {
+                    return Err(#krate::JsonError::new(&format!("{}{}", path, pointer), "enums can only be set as a whole"));
+                }
+                *self = Self::__from_json_at(&value, path)?;
+                Ok(())
+            }
+        }
+
+        impl #krate::JsonPointer for #name {
+            fn json_get(&self, pointer: &str) -> Option<#krate::serde_json::Value> {
+                self.to_json().pointer(pointer).cloned()
+            }
+            fn json_set(&mut self, pointer: &str, value: #krate::serde_json::Value) -> Result<(), #krate::JsonError> {
+                self.__json_set_at(pointer, value, "")
+            }
+        }
+    }
+}
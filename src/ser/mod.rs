@@ -0,0 +1,79 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::Ident;
+
+use crate::attrs::ContainerAttrs;
+use crate::fields::{Entry, Mode};
+use crate::utils::crate_path;
+
+// Streams the same keys, in the same order and with the same conversions, as
+// `to_json`, behind `as_json` so the type keeps its own `Serialize`.
+pub fn expand_serialize(
+    name: &Ident,
+    entries: &[Entry],
+    container: &ContainerAttrs,
+) -> TokenStream {
+    let krate = crate_path();
+    // Enveloped output is nested one level down, so it goes through `to_json_map`.
+    if container.envelope.is_some() {
+        return expand_as_json(
+            name,
+            quote! {
+                #krate::serde::Serialize::serialize(&self.to_json_map(), serializer)
+            },
+        );
+    }
+
+    let type_tag = container.type_tag.as_ref().map(|tag| {
+        let (key, value) = (&tag.key, &tag.value);
+        quote! {
+            map.serialize_entry(#key, #value)?;
+        }
+    });
+    let serialize_entries = entries.iter().map(|entry| {
+        entry.render(Mode::Stream, |key, value| {
+            quote! {
+                map.serialize_entry(#key, &#value)?;
+            }
+        })
+    });
+
+    expand_as_json(
+        name,
+        quote! {
+            use #krate::serde::ser::SerializeMap as _;
+            let mut map = serializer.serialize_map(None)?;
+            #type_tag
+            #( #serialize_entries )*
+            map.end()
+        },
+    )
+}
+
+pub fn expand_enum_serialize(name: &Ident) -> TokenStream {
+    let krate = crate_path();
+    expand_as_json(
+        name,
+        quote! {
+            #krate::serde::Serialize::serialize(&self.to_json(), serializer)
+        },
+    )
+}
+
+fn expand_as_json(name: &Ident, body: TokenStream) -> TokenStream {
+    let krate = crate_path();
+    quote! {
+        impl #name {
+            /// Serializes `self` as `to_json` writes it.
+            pub fn as_json(&self) -> #krate::AsJson<'_, Self> {
+                #krate::AsJson::new(self)
+            }
+        }
+
+        impl #krate::__private::SerializeJson for #name {
+            fn serialize_json<S: #krate::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                #body
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{DataStruct, Error, Ident, Visibility};
+
+use crate::attrs::{ContainerAttrs, DisplayFormat};
+use crate::diff::expand_diff;
+use crate::fields::{Entry, JsonField, Mode};
+use crate::merge::{expand_from_json, expand_merge_patch};
+use crate::mongo::{
+    expand_bson_path, expand_bson_schema, expand_indexes, expand_projection, expand_validator,
+};
+use crate::node::expand_node;
+use crate::patch::expand_patch;
+use crate::paths::{
+    expand_field_constants, expand_field_info, expand_filter_and_sort, expand_paths,
+};
+use crate::pointer::expand_pointer;
+use crate::ser::expand_serialize;
+use crate::types::{last_ident, leaf_type, to_value, Scalar};
+use crate::utils::crate_path;
+
+pub fn expand_struct(
+    name: &Ident,
+    vis: &Visibility,
+    data: &DataStruct,
+    container: &ContainerAttrs,
+    rename_all_convention: &Option<String>,
+) -> syn::Result<TokenStream> {
+    let krate = crate_path();
+    let fields = JsonField::collect(&data.fields, rename_all_convention, container)?;
+
+    let entries = Entry::ordered(&fields, &container.computed)?;
+    Entry::check_keys(
+        &entries,
+        container.type_tag.as_ref().map(|tag| tag.key.as_str()),
+    )?;
+    let inserts = entries
+        .iter()
+        .map(|entry| {
+            entry.render(Mode::Scoped, |key, value| {
+                quote! {
+                    map.insert(#key.to_string(), #value);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Keys the context leaves out are skipped before their value is built.
+    let context_inserts = entries
+        .iter()
+        .map(|entry| {
+            entry.render(Mode::Context, |key, value| {
+                quote! {
+                    if #krate::JsonContext::include_field(ctx, stringify!(#name), #key) {
+                        map.insert(#key.to_string(), #value);
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    let context = match &container.context {
+        Some(context) => quote! { #context },
+        None => quote! { C },
+    };
+    // Without a context type of its own, the struct takes any context its
+    // nested `#[json]` types take.
+    let (context_generics, context_bounds) = match &container.context {
+        Some(_) => (None, None),
+        None => {
+            let nested = fields
+                .iter()
+                .filter(|field| field.shape.leaf() == Scalar::Nested)
+                .map(|field| leaf_type(field.ty))
+                // A recursive field is covered by the impl being generated.
+                .filter(|ty| last_ident(ty) != Some(name));
+            (
+                Some(quote! { <C: #krate::JsonContext + ?Sized> }),
+                Some(quote! { where #( #nested: #krate::__private::JsonNodeWith<C>, )* }),
+            )
+        }
+    };
+
+    let mut references = fields.iter().filter(|field| field.reference);
+    let reference = match (references.next(), references.next()) {
+        (_, Some(extra)) => {
+            return Err(Error::new_spanned(
+                extra.ident,
+                "only one field can be marked #[json(reference)]",
+            ))
+        }
+        (Some(field), None) => {
+            let ident = field.ident;
+            to_value(&field.shape, quote!(&self.#ident))
+        }
+        (None, None) => quote! { #krate::serde_json::Value::Null },
+    };
+
+    let type_tag = container.type_tag.as_ref().map(|tag| {
+        let (key, value) = (&tag.key, &tag.value);
+        quote! {
+            map.insert(#key.to_string(), #krate::serde_json::Value::String(#value.to_string()));
+        }
+    });
+    // The envelope wraps the finished map; nested types wrap themselves the same way.
+    let wrapped = match &container.envelope {
+        Some(envelope) => {
+            let key = &envelope.key;
+            let siblings = envelope.siblings.iter().map(|(sibling, value)| {
+                quote! {
+                    envelope.insert(#sibling.to_string(), #krate::serde_json::json!(#value));
+                }
+            });
+            quote! {
+                let mut envelope: #krate::indexmap::IndexMap<String, #krate::serde_json::Value> = #krate::indexmap::IndexMap::new();
+                envelope.insert(#key.to_string(), #krate::serde_json::Value::Object(map.into_iter().collect()));
+                #( #siblings )*
+                envelope
+            }
+        }
+        None => quote! { map },
+    };
+    // Both ways of rendering build the same map, tag and envelope around their inserts.
+    let map_with = |inserts: &[TokenStream]| {
+        quote! {
+            let mut map: #krate::indexmap::IndexMap<String, #krate::serde_json::Value> = #krate::indexmap::IndexMap::new();
+
+            #type_tag
+            #( #inserts )*
+
+            #wrapped
+        }
+    };
+    let map = map_with(&inserts);
+    let context_map = map_with(&context_inserts);
+
+    let display = container.display.map(|format| {
+        let body = match format {
+            DisplayFormat::Compact => quote! {
+                f.write_str(&self.to_json_string())
+            },
+            DisplayFormat::Pretty => quote! {
+                f.write_str(&#krate::serde_json::to_string_pretty(&self.to_json_map()).expect("Failed to serialize to JSON"))
+            },
+            DisplayFormat::Redacted => quote! {
+                let redacted: #krate::indexmap::IndexMap<String, &str> = self
+                    .to_json_map()
+                    .into_keys()
+                    .map(|key| (key, "[redacted]"))
+                    .collect();
+                f.write_str(&#krate::serde_json::to_string(&redacted).expect("Failed to serialize to JSON"))
+            },
+        };
+
+        quote! {
+            impl ::std::fmt::Display for #name {
+                fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                    #body
+                }
+            }
+        }
+    });
+
+    let diff = expand_diff(&fields, container);
+    let node = expand_node(
+        name,
+        quote! {
+            #krate::serde_json::Value::Object(self.__json_map_as(render).into_iter().collect())
+        },
+        container.context.as_ref(),
+        context_bounds.as_ref(),
+    );
+    let field_constants = expand_field_constants(&fields);
+    let field_info = expand_field_info(name, &fields);
+    let paths = container.mongo.then(|| expand_paths(name, vis, &fields));
+    let filter_and_sort = container
+        .mongo
+        .then(|| expand_filter_and_sort(name, vis, &fields));
+    let from_json = expand_from_json(&fields, container);
+    let merge_patch = expand_merge_patch(&fields, container);
+    let pointer = expand_pointer(name, &fields, container);
+    let bson_path = expand_bson_path(&fields);
+    let bson_schema = expand_bson_schema(&fields);
+    let mongo = if container.mongo {
+        let projection = expand_projection(&fields);
+        let validator = expand_validator();
+        let indexes = expand_indexes(&container.indexes, &fields)?;
+        Some(quote! {
+            #projection
+            #validator
+            #indexes
+        })
+    } else {
+        let declared = container
+            .indexes
+            .iter()
+            .chain(fields.iter().flat_map(|field| &field.indexes))
+            .next();
+        if let Some(index) = declared {
+            return Err(Error::new_spanned(
+                &index.keys[0].0,
+                "indexes need #[json(mongo)] on the struct",
+            ));
+        }
+        None
+    };
+    let patch = container
+        .patch
+        .then(|| expand_patch(name, vis, &fields, container));
+    let serialize = container
+        .serialize
+        .then(|| expand_serialize(name, &entries, container));
+
+    Ok(quote! {
+        impl #name {
+            #field_constants
+
+            pub fn to_json_map(&self) -> #krate::indexmap::IndexMap<String, #krate::serde_json::Value> {
+                self.__json_map_as(#krate::__private::Render::Full)
+            }
+            /// Like `to_json`, but nested values more than `max_depth` levels
+            /// down are replaced by their `#[json(reference)]` field, or `null`.
+            pub fn to_json_with_depth(&self, max_depth: usize) -> #krate::serde_json::Value {
+                #krate::serde_json::Value::Object(self.__json_map_as(#krate::__private::Render::Depth(max_depth)).into_iter().collect())
+            }
+            /// The output of API `version`: fields marked `since`/`until` appear only
+            /// in the versions they cover, and `rename_in` keys take their old names.
+            pub fn to_json_version(&self, version: u32) -> #krate::serde_json::Value {
+                #krate::serde_json::Value::Object(self.__json_map_as(#krate::__private::Render::Version(version)).into_iter().collect())
+            }
+            #[doc(hidden)]
+            pub fn __json_reference(&self) -> #krate::serde_json::Value {
+                #reference
+            }
+            #[doc(hidden)]
+            #[allow(unused_variables)]
+            pub fn __json_map_as(&self, render: #krate::__private::Render) -> #krate::indexmap::IndexMap<String, #krate::serde_json::Value> {
+                #map
+            }
+            /// Like `to_json`, but only with the keys `ctx` lets through, and with
+            /// `ctx` passed on to nested values.
+            #[allow(unused_variables)]
+            pub fn to_json_with #context_generics(&self, ctx: &#context) -> #krate::serde_json::Value #context_bounds {
+                let map = { #context_map };
+                #krate::serde_json::Value::Object(map.into_iter().collect())
+            }
+            pub fn to_json_string(&self) -> String {
+                #krate::serde_json::to_string(&self.to_json_map()).expect("Failed to serialize to JSON")
+            }
+            pub fn to_json(&self) -> #krate::serde_json::Value {
+                #krate::serde_json::Value::Object(self.to_json_map().into_iter().collect())
+            }
+
+            #diff
+            #from_json
+            #merge_patch
+            #bson_path
+            #bson_schema
+            #mongo
+        }
+
+        #node
+        #pointer
+        #field_info
+        #display
+        #patch
+        #serialize
+        #paths
+        #filter_and_sort
+    })
+}
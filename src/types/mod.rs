@@ -0,0 +1,435 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{GenericArgument, Ident, PathArguments, PathSegment, Type};
+
+use crate::utils::crate_path;
+
+/// Leaf types the derive knows how to render by itself.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Scalar {
+    ObjectId,
+    DateTime,
+    /// `serde_json::Value`, inserted as-is.
+    JsonValue,
+    /// `Box<serde_json::value::RawValue>`.
+    RawValue,
+    /// A `String` holding JSON text, marked `#[json(raw)]`.
+    RawJson,
+    Nested,
+    Other,
+}
+
+/// A field type broken down into the wrappers the derive walks through.
+#[derive(Clone)]
+pub enum Shape {
+    Scalar(Scalar),
+    Option(Box<Shape>),
+    Vec(Box<Shape>),
+    Box(Box<Shape>),
+}
+
+impl Shape {
+    pub fn leaf(&self) -> Scalar {
+        match self {
+            Shape::Scalar(scalar) => *scalar,
+            Shape::Option(inner) | Shape::Vec(inner) | Shape::Box(inner) => inner.leaf(),
+        }
+    }
+}
+
+impl Scalar {
+    /// Whether the leaf's own `Serialize` impl already writes the JSON `to_json` wants.
+    pub fn is_serialize(self) -> bool {
+        matches!(self, Scalar::JsonValue | Scalar::RawValue)
+    }
+}
+
+pub fn last_ident(ty: &Type) -> Option<&Ident> {
+    last_segment(ty).map(|segment| &segment.ident)
+}
+
+fn last_segment(ty: &Type) -> Option<&PathSegment> {
+    if let Type::Path(type_path) = ty {
+        if type_path.qself.is_none() {
+            return type_path.path.segments.last();
+        }
+    }
+    None
+}
+
+fn first_generic(segment: &PathSegment) -> Option<&Type> {
+    if let PathArguments::AngleBracketed(args) = &segment.arguments {
+        if let Some(GenericArgument::Type(inner_ty)) = args.args.first() {
+            return Some(inner_ty);
+        }
+    }
+    None
+}
+
+fn classify_scalar(
+    segment: &PathSegment,
+    qualifier: Option<&Ident>,
+    nested: bool,
+    raw: bool,
+) -> Scalar {
+    let ident = &segment.ident;
+    let has_generics = !segment.arguments.is_empty();
+    // `Value` is a common type name, so it is only passed through when written
+    // with its crate, as in `serde_json::Value`.
+    let from_serde_json =
+        matches!(qualifier, Some(qualifier) if qualifier == "serde_json" || qualifier == "value");
+
+    if ident == "ObjectId" {
+        return Scalar::ObjectId;
+    }
+    if ident == "DateTime" && !has_generics {
+        return Scalar::DateTime;
+    }
+    if ident == "Value" && from_serde_json {
+        return Scalar::JsonValue;
+    }
+    if raw && ident == "String" {
+        return Scalar::RawJson;
+    }
+    if nested {
+        return Scalar::Nested;
+    }
+    Scalar::Other
+}
+
+// Whether `segment` is one of the wrappers `classify` looks through.
+fn is_wrapper(segment: &PathSegment) -> bool {
+    segment.ident == "Option" || segment.ident == "Vec" || segment.ident == "Box"
+}
+
+// `RawValue` is unsized, so it only ever appears boxed and the box is the leaf.
+fn is_raw_value_box(segment: &PathSegment) -> bool {
+    segment.ident == "Box"
+        && first_generic(segment)
+            .and_then(last_ident)
+            .is_some_and(|ident| ident == "RawValue")
+}
+
+// The path segment before the last one, as in the `serde_json` of `serde_json::Value`.
+fn qualifier(ty: &Type) -> Option<&Ident> {
+    if let Type::Path(type_path) = ty {
+        let segments = &type_path.path.segments;
+        if segments.len() > 1 {
+            return Some(&segments[segments.len() - 2].ident);
+        }
+    }
+    None
+}
+
+/// Breaks `ty` down into `Option`/`Vec`/`Box` wrappers around a known leaf.
+/// `nested` marks the leaf as a type that derives `ToJson` itself, and `raw`
+/// a `String` leaf that holds JSON text.
+pub fn classify(ty: &Type, nested: bool, raw: bool) -> Shape {
+    let segment = match last_segment(ty) {
+        Some(segment) => segment,
+        None => return Shape::Scalar(Scalar::Other),
+    };
+
+    if is_wrapper(segment) {
+        if let Some(inner_ty) = first_generic(segment) {
+            if is_raw_value_box(segment) {
+                return Shape::Scalar(Scalar::RawValue);
+            }
+            let inner = Box::new(classify(inner_ty, nested, raw));
+            return if segment.ident == "Option" {
+                Shape::Option(inner)
+            } else if segment.ident == "Vec" {
+                Shape::Vec(inner)
+            } else {
+                Shape::Box(inner)
+            };
+        }
+    }
+
+    Shape::Scalar(classify_scalar(segment, qualifier(ty), nested, raw))
+}
+
+/// The `T` of an `Option<T>`.
+pub fn option_inner(ty: &Type) -> Option<&Type> {
+    let segment = last_segment(ty)?;
+    if segment.ident == "Option" {
+        return first_generic(segment);
+    }
+    None
+}
+
+/// The `T` of a `Vec<T>`.
+pub fn vec_element(ty: &Type) -> Option<&Type> {
+    let segment = last_segment(ty)?;
+    if segment.ident == "Vec" {
+        return first_generic(segment);
+    }
+    None
+}
+
+/// The `T` of an `Option<T>`, `Vec<T>` or `Box<T>`.
+pub fn wrapped_type(ty: &Type) -> Option<&Type> {
+    let segment = last_segment(ty)?;
+    if is_wrapper(segment) {
+        return first_generic(segment);
+    }
+    None
+}
+
+/// The type left once `Option`/`Vec`/`Box` wrappers are stripped from `ty`.
+pub fn leaf_type(ty: &Type) -> &Type {
+    if let Some(segment) = last_segment(ty) {
+        if is_wrapper(segment) && !is_raw_value_box(segment) {
+            if let Some(inner_ty) = first_generic(segment) {
+                return leaf_type(inner_ty);
+            }
+        }
+    }
+    ty
+}
+
+/// Whether `ty`, past an `Option`, is a collection or string that can be empty.
+pub fn is_emptiable(ty: &Type) -> bool {
+    let ty = option_inner(ty).unwrap_or(ty);
+    matches!(
+        last_ident(ty).map(|ident| ident.to_string()).as_deref(),
+        Some(
+            "Vec"
+                | "VecDeque"
+                | "String"
+                | "HashMap"
+                | "BTreeMap"
+                | "IndexMap"
+                | "HashSet"
+                | "BTreeSet"
+                | "IndexSet"
+                | "Document"
+        )
+    )
+}
+
+fn scalar_to_value(scalar: Scalar, value: &TokenStream, render: Render) -> TokenStream {
+    let krate = crate_path();
+    let value = quote!((#value));
+    match scalar {
+        Scalar::ObjectId => quote! {
+            #krate::serde_json::Value::String(#value.to_hex())
+        },
+        Scalar::DateTime => quote! {
+            #krate::serde_json::Value::String(#value.try_to_rfc3339_string().expect("try_to_rfc3339_string err"))
+        },
+        Scalar::JsonValue => quote! {
+            ::std::clone::Clone::clone(#value)
+        },
+        Scalar::RawValue => quote! {
+            #krate::serde_json::from_str::<#krate::serde_json::Value>(#value.get())
+                .expect("RawValue holds valid JSON")
+        },
+        // Text that is not valid JSON is kept as a plain string.
+        Scalar::RawJson => quote! {
+            #krate::serde_json::from_str::<#krate::serde_json::Value>(#value)
+                .unwrap_or_else(|_| #krate::serde_json::Value::String(::std::clone::Clone::clone(#value)))
+        },
+        Scalar::Nested => match render {
+            Render::Value => quote! {
+                #value.to_json()
+            },
+            Render::Scoped => quote! {
+                #krate::__private::nested_value(#value, render)
+            },
+            Render::Context | Render::AnyContext => quote! {
+                #krate::__private::JsonNodeWith::json_value_with(#value, ctx)
+            },
+        },
+        Scalar::Other => match render {
+            Render::Value => probe(
+                quote!(Probe::of(#value)),
+                quote!(to_value(#value, #krate::__private::Render::Full)),
+            ),
+            Render::Scoped => probe(quote!(Probe::of(#value)), quote!(to_value(#value, render))),
+            Render::Context => probe(
+                quote!(Probe::of(#value)),
+                quote!(to_value_with(#value, ctx)),
+            ),
+            // Left to inference, `C` would be taken from the only `JsonNodeWith`
+            // impl of a type tied to its own context, hiding the missing bound.
+            Render::AnyContext => probe(
+                quote!(Probe::of(#value)),
+                quote!(to_value_with::<C>(#value, ctx)),
+            ),
+        },
+    }
+}
+
+/// Calls `call` on `probe`, a `Probe` from the runtime, so that the call goes
+/// to the `ToJson` impl of the type it names when there is one, to its
+/// `JsonScalar` impl when it is a known leaf and through serde otherwise.
+pub fn probe(probe: TokenStream, call: TokenStream) -> TokenStream {
+    let krate = crate_path();
+    quote! {
+        {
+            #[allow(unused_imports)]
+            use #krate::__private::{NodeOps as _, PlainOps as _, ScalarOps as _};
+            (&&&#krate::__private::#probe).#call
+        }
+    }
+}
+
+/// How nested types are rendered.
+#[derive(Clone, Copy)]
+pub enum Render {
+    /// In full, with `to_json`.
+    Value,
+    /// As the runtime `render: Render` in scope says, which covers depth and version.
+    Scoped,
+    /// With `to_json_with`, passing on the `ctx` in scope.
+    Context,
+    /// Like `Context`, for structs whose `ctx` is of any type `C`.
+    AnyContext,
+}
+
+/// Builds an expression turning `value` (a reference to a field of type
+/// `shape`) into a `serde_json::Value`.
+pub fn to_value(shape: &Shape, value: TokenStream) -> TokenStream {
+    shape_to_value(shape, value, Render::Value)
+}
+
+/// Like `to_value`, rendering nested types as `render` says.
+pub fn to_value_with(shape: &Shape, value: TokenStream, render: Render) -> TokenStream {
+    shape_to_value(shape, value, render)
+}
+
+fn shape_to_value(shape: &Shape, value: TokenStream, render: Render) -> TokenStream {
+    let krate = crate_path();
+    match shape {
+        Shape::Scalar(scalar) => scalar_to_value(*scalar, &value, render),
+        Shape::Box(inner) => shape_to_value(inner, quote!(&**#value), render),
+        Shape::Option(inner) => {
+            let inner = shape_to_value(inner, quote!(value), render);
+            quote! {
+                match #value {
+                    Some(value) => #inner,
+                    None => #krate::serde_json::Value::Null,
+                }
+            }
+        }
+        Shape::Vec(inner) => {
+            let inner = shape_to_value(inner, quote!(value), render);
+            quote! {
+                #krate::serde_json::Value::Array((#value).iter().map(|value| #inner).collect())
+            }
+        }
+    }
+}
+
+fn scalar_from_value(
+    scalar: Scalar,
+    leaf_ty: &Type,
+    value: &TokenStream,
+    path: &TokenStream,
+) -> TokenStream {
+    let krate = crate_path();
+    let from_string = match scalar {
+        Scalar::ObjectId => quote! {
+            #krate::mongodb::bson::oid::ObjectId::parse_str(string)
+        },
+        Scalar::DateTime => quote! {
+            #krate::mongodb::bson::DateTime::parse_rfc3339_str(string)
+        },
+        Scalar::JsonValue => {
+            return quote! {
+                Ok::<_, #krate::JsonError>(::std::clone::Clone::clone(#value))
+            }
+        }
+        Scalar::RawValue => {
+            return quote! {
+                #krate::serde_json::value::to_raw_value(#value)
+                    .map_err(|err| #krate::JsonError::new(#path, err))
+            }
+        }
+        // A plain string is what `to_json` wrote for text that was not JSON.
+        Scalar::RawJson => {
+            return quote! {
+                Ok::<_, #krate::JsonError>(match #value {
+                    #krate::serde_json::Value::String(string) => ::std::clone::Clone::clone(string),
+                    other => other.to_string(),
+                })
+            }
+        }
+        Scalar::Nested => {
+            return quote! {
+                <#leaf_ty>::__from_json_at(#value, #path)
+            }
+        }
+        // Types that derive ToJson without being marked `#[json]` are still read back with it.
+        Scalar::Other => {
+            return probe(
+                quote!(Probe::<#leaf_ty>::new()),
+                quote! {
+                    read_value(#value, #path, || {
+                        #[allow(unused_imports)]
+                        use #krate::__private::{DeserializeValue as _, NotDeserializable as _};
+                        (&#krate::__private::FromJsonValue::<#leaf_ty>::new(#value, #path)).deserialize_value()
+                    })
+                },
+            )
+        }
+    };
+
+    quote! {
+        #krate::__private::expect_str(#value, #path).and_then(|string| {
+            #from_string.map_err(|err| #krate::JsonError::new(#path, err))
+        })
+    }
+}
+
+/// Builds an expression turning `value` (a `&serde_json::Value` found at the
+/// JSON Pointer `path`) back into a field of type `ty`, as a
+/// `Result<_, to_json::JsonError>`.
+pub fn from_value(shape: &Shape, ty: &Type, value: TokenStream, path: TokenStream) -> TokenStream {
+    shape_from_value(shape, leaf_type(ty), value, path)
+}
+
+fn shape_from_value(
+    shape: &Shape,
+    leaf_ty: &Type,
+    value: TokenStream,
+    path: TokenStream,
+) -> TokenStream {
+    let krate = crate_path();
+    match shape {
+        Shape::Scalar(scalar) => scalar_from_value(*scalar, leaf_ty, &value, &path),
+        Shape::Box(inner) => {
+            let inner = shape_from_value(inner, leaf_ty, value, path);
+            quote! {
+                (#inner).map(::std::boxed::Box::new)
+            }
+        }
+        Shape::Option(inner) => {
+            let inner = shape_from_value(inner, leaf_ty, value.clone(), path);
+            quote! {
+                if (#value).is_null() {
+                    Ok(None)
+                } else {
+                    (#inner).map(Some)
+                }
+            }
+        }
+        Shape::Vec(inner) => {
+            let inner = shape_from_value(inner, leaf_ty, quote!(value), quote!(&path));
+            quote! {
+                match #value {
+                    #krate::serde_json::Value::Array(items) => items
+                        .iter()
+                        .enumerate()
+                        .map(|(index, value)| {
+                            let path = format!("{}/{}", #path, index);
+                            #inner
+                        })
+                        .collect::<Result<Vec<_>, #krate::JsonError>>(),
+                    _ => Err(#krate::JsonError::new(#path, "expected an array")),
+                }
+            }
+        }
+    }
+}
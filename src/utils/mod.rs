@@ -1,35 +1,45 @@
-use syn::{Field, GenericArgument, Ident, PathArguments, Type};
+use std::cell::RefCell;
 
-pub fn get_field_name(field: &Field) -> String {
-    for attr in &field.attrs {
-        if attr.path.is_ident("serde") {
-            if let Ok(meta) = attr.parse_meta() {
-                if let syn::Meta::List(meta_list) = meta {
-                    for nested_meta in meta_list.nested {
-                        if let syn::NestedMeta::Meta(syn::Meta::NameValue(m)) = nested_meta {
-                            if m.path.is_ident("rename") {
-                                if let syn::Lit::Str(lit_str) = &m.lit {
-                                    return lit_str.value();
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Field, Path, Variant};
+
+thread_local! {
+    static CRATE_PATH: RefCell<Option<Path>> = const { RefCell::new(None) };
+}
+
+// Sets the path generated code reaches the runtime crate through, for the
+// derive being expanded. The default `::to_json` comes back when the guard is
+// dropped, even if the expansion panics.
+pub fn set_crate_path(path: Option<Path>) -> CratePathGuard {
+    CRATE_PATH.with(|crate_path| *crate_path.borrow_mut() = path);
+    CratePathGuard
+}
+
+pub struct CratePathGuard;
+
+impl Drop for CratePathGuard {
+    fn drop(&mut self) {
+        CRATE_PATH.with(|crate_path| *crate_path.borrow_mut() = None);
     }
-    field.ident.as_ref().unwrap().to_string()
 }
 
-pub fn field_contains_rename(field: &Field) -> bool {
-    for attr in &field.attrs {
+pub fn crate_path() -> TokenStream {
+    CRATE_PATH.with(|crate_path| match &*crate_path.borrow() {
+        Some(path) => quote!(#path),
+        None => quote!(::to_json),
+    })
+}
+
+pub fn get_serde_rename(attrs: &[syn::Attribute]) -> Option<String> {
+    for attr in attrs {
         if attr.path.is_ident("serde") {
-            if let Ok(meta) = attr.parse_meta() {
-                if let syn::Meta::List(meta_list) = meta {
-                    for nested_meta in meta_list.nested {
-                        if let syn::NestedMeta::Meta(syn::Meta::NameValue(m)) = nested_meta {
-                            if m.path.is_ident("rename") {
-                                return true;
+            if let Ok(syn::Meta::List(meta_list)) = attr.parse_meta() {
+                for nested_meta in meta_list.nested {
+                    if let syn::NestedMeta::Meta(syn::Meta::NameValue(m)) = nested_meta {
+                        if m.path.is_ident("rename") {
+                            if let syn::Lit::Str(lit_str) = &m.lit {
+                                return Some(lit_str.value());
                             }
                         }
                     }
@@ -37,80 +47,51 @@ pub fn field_contains_rename(field: &Field) -> bool {
             }
         }
     }
-    false
+    None
 }
 
-pub fn optional_sub_ident(ty: &Type) -> Option<Ident> {
-    if let Type::Path(type_path) = ty {
-        if type_path.path.segments.len() == 1 && type_path.path.segments[0].ident == "Option" {
-            if let PathArguments::AngleBracketed(args) = &type_path.path.segments[0].arguments {
-                if let Some(GenericArgument::Type(inner_ty)) = args.args.first() {
-                    if let Type::Path(inner_type_path) = inner_ty {
-                        if inner_type_path.path.segments.len() == 1 {
-                            return Some(inner_type_path.path.segments[0].clone().ident);
-                        }
-                    }
-                }
-            }
-        }
-    }
+/// The `///` doc comment in `attrs`, one line per line of it.
+pub fn get_docs(attrs: &[syn::Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(syn::Meta::NameValue(syn::MetaNameValue {
+                lit: syn::Lit::Str(lit_str),
+                ..
+            })) => Some(lit_str.value()),
+            _ => None,
+        })
+        .map(|line| line.strip_prefix(' ').map(str::to_string).unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-    None
+pub fn get_field_name(field: &Field) -> String {
+    get_serde_rename(&field.attrs).unwrap_or_else(|| field.ident.as_ref().unwrap().to_string())
 }
 
-pub fn vec_sub_ident(ty: &Type) -> Option<Ident> {
-    if let Type::Path(type_path) = ty {
-        if type_path.path.segments.len() == 1 && type_path.path.segments[0].ident == "Vec" {
-            if let PathArguments::AngleBracketed(args) = &type_path.path.segments[0].arguments {
-                if let Some(GenericArgument::Type(inner_ty)) = args.args.first() {
-                    if let Type::Path(inner_type_path) = inner_ty {
-                        if inner_type_path.path.segments.len() == 1 {
-                            return Some(inner_type_path.path.segments[0].clone().ident);
-                        }
-                    }
-                }
-            }
-        }
-        if type_path.path.segments.len() == 1 && type_path.path.segments[0].ident == "Option" {
-            if let PathArguments::AngleBracketed(args) = &type_path.path.segments[0].arguments {
-                if let Some(GenericArgument::Type(inner_ty)) = args.args.first() {
-                    if let Type::Path(inner_type_path) = inner_ty {
-                        if inner_type_path.path.segments.len() == 1
-                            && inner_type_path.path.segments[0].ident == "Vec"
-                        {
-                            if let PathArguments::AngleBracketed(args) =
-                                &inner_type_path.path.segments[0].arguments
-                            {
-                                if let Some(GenericArgument::Type(inner_ty)) = args.args.first() {
-                                    if let Type::Path(inner_inner_type_path) = inner_ty {
-                                        if inner_inner_type_path.path.segments.len() == 1 {
-                                            return Some(
-                                                inner_inner_type_path.path.segments[0]
-                                                    .clone()
-                                                    .ident,
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
+pub fn field_contains_rename(field: &Field) -> bool {
+    get_serde_rename(&field.attrs).is_some()
+}
+
+// Final JSON key of a field: its serde rename, or the rename_all convention applied to its name.
+pub fn get_final_name(field: &Field, rename_all_convention: &Option<String>) -> String {
+    let field_name_str = get_field_name(field);
+
+    if field_contains_rename(field) {
+        return field_name_str;
     }
 
-    None
+    match rename_all_convention {
+        Some(convention) => apply_rename_all(convention, &field_name_str),
+        None => field_name_str,
+    }
 }
 
-// Check if a field has the #[json] attribute.
-pub fn has_json_attr(field: &Field) -> bool {
-    for attr in &field.attrs {
-        if attr.path.is_ident("json") {
-            return true;
-        }
-    }
-    false
+// A JSON key escaped as a JSON Pointer reference token.
+pub fn pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
 }
 
 pub fn get_rename_all(attrs: &[syn::Attribute]) -> Option<String> {
@@ -138,10 +119,41 @@ pub fn apply_rename_all(convention: &str, field_name: &str) -> String {
         "camelCase" => convert_to_camel_case(field_name),
         "PascalCase" => convert_to_pascal_case(field_name),
         "SCREAMING_SNAKE_CASE" => convert_to_screaming_snake_case(field_name),
+        "lowercase" => field_name.to_ascii_lowercase(),
+        "UPPERCASE" => field_name.to_ascii_uppercase(),
+        "kebab-case" => convert_to_snake_case(field_name).replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => convert_to_screaming_snake_case(field_name).replace('_', "-"),
         _ => field_name.to_string(),
     }
 }
 
+// Final string of a unit variant, following serde's rules for enum variants.
+pub fn get_variant_name(variant: &Variant, rename_all_convention: &Option<String>) -> String {
+    if let Some(rename) = get_serde_rename(&variant.attrs) {
+        return rename;
+    }
+
+    let variant_name = variant.ident.to_string();
+    match rename_all_convention.as_deref() {
+        Some("lowercase") => variant_name.to_ascii_lowercase(),
+        Some("UPPERCASE") => variant_name.to_ascii_uppercase(),
+        Some("camelCase") => {
+            let mut chars = variant_name.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+                None => variant_name,
+            }
+        }
+        Some("snake_case") => convert_to_snake_case(&variant_name),
+        Some("SCREAMING_SNAKE_CASE") => convert_to_screaming_snake_case(&variant_name),
+        Some("kebab-case") => convert_to_snake_case(&variant_name).replace('_', "-"),
+        Some("SCREAMING-KEBAB-CASE") => {
+            convert_to_screaming_snake_case(&variant_name).replace('_', "-")
+        }
+        _ => variant_name,
+    }
+}
+
 pub fn convert_to_snake_case(name: &str) -> String {
     let mut snake_case = String::new();
     for (i, c) in name.chars().enumerate() {
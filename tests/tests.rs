@@ -1,7 +1,10 @@
+#![allow(clippy::print_with_newline)]
+
 #[cfg(test)]
 mod tests {
     use serde::{Deserialize, Serialize};
     use serde_json::json;
+    use to_json::{JsonPointer, ToJsonFields};
     use to_json_macro::ToJson;
 
     use bson::{oid::ObjectId, DateTime};
@@ -17,6 +20,7 @@ mod tests {
 
     #[derive(ToJson, Serialize, Clone, Deserialize)]
     #[serde(rename_all = "camelCase")]
+    #[json(mongo)]
     struct MyStruct {
         #[serde(rename = "_id")]
         _id: ObjectId,
@@ -44,6 +48,7 @@ mod tests {
 
     #[derive(ToJson, Serialize, Clone, Deserialize)]
     #[serde(rename_all = "camelCase")]
+    #[json(mongo)]
     struct Two {
         #[serde(rename = "_id")]
         id: ObjectId,
@@ -109,4 +114,1473 @@ mod tests {
         assert_eq!(json_value["name"], json!(my_struct.name));
         assert_eq!(json_value["value"], json!(my_struct.value));
     }
+
+    #[derive(ToJson, Serialize)]
+    struct Wrappers {
+        ids: Option<Vec<ObjectId>>,
+        dates: Vec<Option<bson::DateTime>>,
+        #[json]
+        twos: Option<Vec<Two>>,
+    }
+
+    #[test]
+    fn test_nested_wrappers() {
+        let id = ObjectId::new();
+        let wrappers = Wrappers {
+            ids: Some(vec![id]),
+            dates: vec![None, Some(DateTime::from_millis(0))],
+            twos: None,
+        };
+
+        let json_value = wrappers.to_json();
+
+        assert_eq!(json_value["ids"], json!([id.to_hex()]));
+        assert_eq!(json_value["dates"], json!([null, "1970-01-01T00:00:00Z"]));
+        assert_eq!(json_value["twos"], json!(null));
+    }
+
+    #[cfg(all(
+        feature = "chrono",
+        feature = "time",
+        feature = "uuid",
+        feature = "rust_decimal",
+        feature = "url"
+    ))]
+    #[test]
+    fn test_adapters() {
+        use std::str::FromStr;
+
+        type Stamp = chrono::DateTime<chrono::Utc>;
+
+        #[derive(ToJson)]
+        struct Adapters {
+            chrono_date: chrono::DateTime<chrono::Utc>,
+            stamps: Vec<Stamp>,
+            time_date: time::OffsetDateTime,
+            uuid: uuid::Uuid,
+            bson_uuid: bson::Uuid,
+            binary: Option<bson::Binary>,
+            price: rust_decimal::Decimal,
+            prices: Vec<rust_decimal::Decimal>,
+            homepage: url::Url,
+        }
+
+        let uuid = uuid::Uuid::from_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap();
+        let adapters = Adapters {
+            chrono_date: chrono::DateTime::from_timestamp(0, 0).unwrap(),
+            stamps: vec![chrono::DateTime::from_timestamp(0, 0).unwrap()],
+            time_date: time::macros::datetime!(2024-01-02 03:04:05 UTC),
+            uuid,
+            bson_uuid: bson::Uuid::from_bytes(*uuid.as_bytes()),
+            binary: Some(bson::Binary::from_uuid(bson::Uuid::from_bytes(
+                *uuid.as_bytes(),
+            ))),
+            price: rust_decimal::Decimal::from_str("0.10").unwrap(),
+            prices: vec![rust_decimal::Decimal::from_str("12345678901234567890.5").unwrap()],
+            homepage: url::Url::parse("https://example.com/a").unwrap(),
+        };
+
+        let json_value = adapters.to_json();
+
+        assert_eq!(json_value["chrono_date"], json!("1970-01-01T00:00:00Z"));
+        assert_eq!(json_value["stamps"], json!(["1970-01-01T00:00:00Z"]));
+        assert_eq!(json_value["time_date"], json!("2024-01-02T03:04:05Z"));
+        assert_eq!(json_value["uuid"], json!(uuid.to_string()));
+        assert_eq!(json_value["bson_uuid"], json!(uuid.to_string()));
+        assert_eq!(json_value["binary"], json!(uuid.to_string()));
+        assert_eq!(json_value["price"], json!("0.10"));
+        assert_eq!(json_value["prices"], json!(["12345678901234567890.5"]));
+        assert_eq!(json_value["homepage"], json!("https://example.com/a"));
+
+        let round_trip = Adapters::from_json(&json_value).unwrap();
+        assert_eq!(round_trip.to_json(), json_value);
+    }
+
+    #[derive(ToJson)]
+    struct Compact {
+        name: String,
+        value: i32,
+    }
+
+    #[derive(ToJson)]
+    #[json(display = "pretty")]
+    struct Pretty {
+        name: String,
+    }
+
+    #[derive(ToJson)]
+    #[json(display = "redacted")]
+    struct Redacted {
+        email: String,
+        password: String,
+    }
+
+    #[derive(ToJson)]
+    #[json(no_display)]
+    struct Custom {
+        name: String,
+    }
+
+    impl std::fmt::Display for Custom {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Custom({})", self.name)
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let compact = Compact {
+            name: "test".to_string(),
+            value: 42,
+        };
+        assert_eq!(compact.to_string(), r#"{"name":"test","value":42}"#);
+
+        let pretty = Pretty {
+            name: "test".to_string(),
+        };
+        assert_eq!(pretty.to_string(), "{\n  \"name\": \"test\"\n}");
+
+        let redacted = Redacted {
+            email: "a@b.c".to_string(),
+            password: "secret".to_string(),
+        };
+        assert_eq!(
+            redacted.to_string(),
+            r#"{"email":"[redacted]","password":"[redacted]"}"#
+        );
+
+        let custom = Custom {
+            name: "test".to_string(),
+        };
+        assert_eq!(custom.to_string(), "Custom(test)");
+        assert_eq!(custom.to_json_string(), r#"{"name":"test"}"#);
+    }
+
+    #[derive(ToJson, Serialize, Debug, PartialEq)]
+    #[serde(rename_all = "SCREAMING-KEBAB-CASE")]
+    enum Role {
+        SuperAdmin,
+        #[serde(rename = "the \"user\"")]
+        User,
+    }
+
+    #[derive(ToJson, Debug, PartialEq)]
+    enum Channel {
+        Email,
+        Sms,
+        #[json(other)]
+        Unknown,
+    }
+
+    #[test]
+    fn test_unit_enum_helpers() {
+        assert_eq!(Role::ALL, &[Role::SuperAdmin, Role::User]);
+        assert_eq!(Role::variant_names(), &["SUPER-ADMIN", "the \"user\""]);
+        assert_eq!(Role::User.get_string(), "the \"user\"");
+        assert_eq!(Role::User.to_string(), "the \"user\"");
+        assert_eq!(Role::User.to_json(), json!("the \"user\""));
+        assert_eq!("SUPER-ADMIN".parse::<Role>(), Ok(Role::SuperAdmin));
+        assert_eq!(Role::try_from("the \"user\""), Ok(Role::User));
+        assert_eq!(Role::from_json(&Role::User.to_json()), Ok(Role::User));
+        assert_eq!(RegisterStatus::Pending.to_json(), json!("pending"));
+
+        let err = "guest".parse::<Role>().unwrap_err();
+        assert_eq!(err.value, "guest");
+        assert_eq!(
+            err.to_string(),
+            "invalid Role `guest`, expected one of: SUPER-ADMIN, the \"user\""
+        );
+        assert!(Role::from_json(&json!(1)).is_err());
+        assert_eq!(
+            Role::from_json(&json!("guest")).unwrap_err(),
+            to_json::JsonError::new("", err)
+        );
+
+        assert_eq!("Sms".parse::<Channel>(), Ok(Channel::Sms));
+        assert_eq!("fax".parse::<Channel>(), Ok(Channel::Unknown));
+
+        assert!(Never::ALL.is_empty());
+        assert!(Never::from_json(&json!("x")).is_err());
+    }
+
+    #[derive(ToJson)]
+    enum Never {}
+
+    #[derive(ToJson, Debug, PartialEq, Clone, Copy)]
+    #[json(repr = "int")]
+    enum LegacyStatus {
+        Draft = 1,
+        Published,
+        #[json(value = 9)]
+        Archived,
+    }
+
+    #[derive(ToJson, Debug, PartialEq)]
+    enum Priority {
+        #[json(value = "P")]
+        High,
+        Low,
+    }
+
+    #[test]
+    fn test_enum_values() {
+        assert_eq!(LegacyStatus::Draft.to_json(), json!(1));
+        assert_eq!(LegacyStatus::Published.to_json(), json!(2));
+        assert_eq!(LegacyStatus::Archived.to_json_string(), "9");
+        assert_eq!(LegacyStatus::Archived.get_string(), "9");
+        assert_eq!(LegacyStatus::Published.to_string(), "2");
+        assert_eq!(LegacyStatus::variant_names(), &["1", "2", "9"]);
+        assert_eq!(
+            LegacyStatus::from_json(&json!(2)),
+            Ok(LegacyStatus::Published)
+        );
+        assert!(LegacyStatus::from_json(&json!(3)).is_err());
+        assert_eq!("9".parse::<LegacyStatus>(), Ok(LegacyStatus::Archived));
+
+        assert_eq!(Priority::High.to_json(), json!("P"));
+        assert_eq!(Priority::Low.to_string(), "Low");
+        assert_eq!(Priority::from_json(&json!("P")), Ok(Priority::High));
+    }
+
+    #[test]
+    fn test_json_diff() {
+        let two = Two {
+            id: ObjectId::new(),
+            test_id2: None,
+            register_status: Some(RegisterStatus::Pending),
+            register_status2: None,
+        };
+        let before = Wrappers {
+            ids: None,
+            dates: vec![Some(DateTime::from_millis(0))],
+            twos: Some(vec![two.clone(), two.clone()]),
+        };
+
+        let new_id = ObjectId::new();
+        let mut changed = two.clone();
+        changed.id = new_id;
+        changed.register_status = Some(RegisterStatus::Complete);
+        let after = Wrappers {
+            ids: Some(vec![new_id]),
+            dates: vec![Some(DateTime::from_millis(0))],
+            twos: Some(vec![two.clone(), changed]),
+        };
+
+        let changes = before.json_diff(&after);
+        let paths: Vec<&str> = changes.iter().map(|change| change.path.as_str()).collect();
+        assert_eq!(paths, ["/ids", "/twos/1/_id", "/twos/1/registerStatus"]);
+        assert_eq!(changes[1].old, Some(json!(two.id.to_hex())));
+        assert_eq!(changes[1].new, Some(json!(new_id.to_hex())));
+        assert!(before.json_diff(&before).is_empty());
+
+        assert_eq!(
+            before.json_patch(&after),
+            json!([
+                { "op": "replace", "path": "/ids", "value": [new_id.to_hex()] },
+                { "op": "replace", "path": "/twos/1/_id", "value": new_id.to_hex() },
+                { "op": "replace", "path": "/twos/1/registerStatus", "value": "complete" },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_apply_merge_patch() {
+        let id = ObjectId::new();
+        let two = Two {
+            id,
+            test_id2: None,
+            register_status: Some(RegisterStatus::Pending),
+            register_status2: None,
+        };
+        let mut wrappers = Wrappers {
+            ids: Some(vec![ObjectId::new()]),
+            dates: vec![],
+            twos: Some(vec![two.clone()]),
+        };
+
+        let new_id = ObjectId::new();
+        wrappers
+            .apply_merge_patch(&json!({
+                "ids": null,
+                "dates": [null, "2024-01-02T03:04:05Z"],
+                "twos": [{ "_id": new_id.to_hex(), "registerStatus": "complete" }],
+            }))
+            .unwrap();
+
+        assert_eq!(wrappers.ids, None);
+        assert_eq!(
+            wrappers.dates,
+            vec![
+                None,
+                Some(DateTime::parse_rfc3339_str("2024-01-02T03:04:05Z").unwrap())
+            ]
+        );
+        let twos = wrappers.twos.as_ref().unwrap();
+        assert_eq!(twos[0].id, new_id);
+        assert!(matches!(
+            twos[0].register_status,
+            Some(RegisterStatus::Complete)
+        ));
+        assert!(twos[0].register_status2.is_none());
+
+        let mut two = two;
+        two.apply_merge_patch(&json!({ "testId2": new_id.to_hex() }))
+            .unwrap();
+        assert_eq!(two.id, id);
+        assert_eq!(two.test_id2, Some(new_id));
+
+        let err = two
+            .apply_merge_patch(&json!({ "_id": "nope" }))
+            .unwrap_err();
+        assert_eq!(err.path, "/_id");
+        let err = two
+            .apply_merge_patch(&json!({ "registerStatus": "unknown" }))
+            .unwrap_err();
+        assert_eq!(err.path, "/registerStatus");
+        let err = two.apply_merge_patch(&json!({ "nope": 1 })).unwrap_err();
+        assert_eq!(err.to_string(), "unknown field `nope`");
+
+        // A failing patch changes nothing, not even the keys before the bad one.
+        let err = two
+            .apply_merge_patch(&json!({ "testId2": null, "registerStatus": "unknown" }))
+            .unwrap_err();
+        assert_eq!(err.path, "/registerStatus");
+        assert_eq!(two.test_id2, Some(new_id));
+    }
+
+    #[derive(ToJson, Serialize, Deserialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    #[json(patch)]
+    struct Profile {
+        #[serde(rename = "_id")]
+        id: ObjectId,
+        display_name: String,
+        nickname: Option<String>,
+        birth_date: Option<DateTime>,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn test_patch_struct() {
+        let mut profile = Profile {
+            id: ObjectId::new(),
+            display_name: "Ann".to_string(),
+            nickname: Some("annie".to_string()),
+            birth_date: None,
+            tags: vec![],
+        };
+
+        let patch = ProfilePatch::from_json(&json!({
+            "displayName": "Anne",
+            "nickname": null,
+            "birthDate": "2000-01-01T00:00:00Z",
+        }))
+        .unwrap();
+
+        assert_eq!(
+            patch.display_name,
+            to_json::PatchField::Value("Anne".to_string())
+        );
+        assert_eq!(patch.nickname, to_json::PatchField::Null);
+        assert!(patch.tags.is_absent());
+
+        let birth_date = DateTime::parse_rfc3339_str("2000-01-01T00:00:00Z").unwrap();
+        assert_eq!(
+            patch.to_set_document(),
+            bson::doc! {
+                "$set": { "displayName": "Anne", "birthDate": birth_date },
+                "$unset": { "nickname": "" },
+            }
+        );
+
+        let id = profile.id;
+        patch.apply_to(&mut profile);
+        assert_eq!(profile.id, id);
+        assert_eq!(profile.display_name, "Anne");
+        assert_eq!(profile.nickname, None);
+        assert_eq!(profile.birth_date, Some(birth_date));
+
+        assert!(ProfilePatch::from_json(&json!({ "displayName": null })).is_err());
+        assert!(ProfilePatch::from_json(&json!({ "unknown": 1 })).is_err());
+        assert!(ProfilePatch::default().to_set_document().is_empty());
+    }
+
+    #[test]
+    fn test_field_paths() {
+        assert_eq!(MyStruct::FIELD_DATE_TIME, "dateTime");
+        assert_eq!(MyStruct::FIELD__ID, "_id");
+        assert_eq!(MyStruct::FIELD_STR_VEC, "strVec");
+        assert_eq!(Two::FIELD_REGISTER_STATUS, "registerStatus");
+
+        assert_eq!(MyStruct::paths().name(), "name");
+        assert_eq!(MyStruct::paths().two().id(), "two._id");
+        assert_eq!(MyStruct::paths().two_opt().test_id2(), "twoOpt.testId2");
+        assert_eq!(
+            MyStruct::paths().two_vec().register_status(),
+            "twoVec.registerStatus"
+        );
+        assert_eq!(MyStruct::paths().two().to_string(), "two");
+    }
+
+    #[test]
+    fn test_filter_and_sort() {
+        let oid = ObjectId::new();
+        let date = DateTime::from_millis(0);
+
+        let filter = MyStruct::filter()
+            .name()
+            .eq("x")
+            .and(MyStruct::filter().date_time().gte(date))
+            .and(MyStruct::filter().str_vec().contains("tag"))
+            .and(MyStruct::filter().two().id().eq(oid));
+        assert_eq!(
+            filter.into_document(),
+            bson::doc! {
+                "$and": [
+                    { "name": "x" },
+                    { "dateTime": { "$gte": date } },
+                    { "strVec": "tag" },
+                    { "two._id": oid },
+                ]
+            }
+        );
+
+        let filter = MyStruct::filter()
+            .test_id()
+            .is_null()
+            .or(MyStruct::filter().value().is_in([1, 2]));
+        assert_eq!(
+            bson::Document::from(filter),
+            bson::doc! { "$or": [{ "testId": null }, { "value": { "$in": [1, 2] } }] }
+        );
+
+        assert_eq!(
+            MyStruct::filter()
+                .two_vec()
+                .register_status()
+                .eq(RegisterStatus::Pending)
+                .into_document(),
+            bson::doc! { "twoVec.registerStatus": "pending" }
+        );
+
+        let sort = MyStruct::sort()
+            .date_time()
+            .desc()
+            .then(MyStruct::sort().name().asc());
+        assert_eq!(
+            sort.into_document(),
+            bson::doc! { "dateTime": -1, "name": 1 }
+        );
+    }
+
+    #[test]
+    fn test_mongo_projection() {
+        assert_eq!(
+            Two::mongo_projection(),
+            bson::doc! { "_id": 1, "testId2": 1, "registerStatus": 1, "registerStatus2": 1 }
+        );
+        assert_eq!(
+            MyStruct::mongo_projection_for(&["name", "two._id", "twoVec.registerStatus"]).unwrap(),
+            bson::doc! { "name": 1, "two._id": 1, "twoVec.registerStatus": 1 }
+        );
+        assert!(MyStruct::mongo_projection_for(&["nope"]).is_err());
+        assert!(MyStruct::mongo_projection_for(&["name.first"]).is_err());
+        assert!(MyStruct::mongo_projection_for(&["two.registerStatus.x"]).is_err());
+    }
+
+    #[test]
+    fn test_mongo_validator() {
+        assert_eq!(
+            Two::mongo_validator(),
+            bson::doc! {
+                "$jsonSchema": {
+                    "bsonType": "object",
+                    "required": ["_id"],
+                    "properties": {
+                        "_id": { "bsonType": "objectId" },
+                        "testId2": { "bsonType": ["null", "objectId"] },
+                        "registerStatus": { "enum": ["pending", "complete", null] },
+                        "registerStatus2": { "enum": ["pending", "complete", null] },
+                    },
+                }
+            }
+        );
+
+        let validator = MyStruct::mongo_validator();
+        let properties = validator
+            .get_document("$jsonSchema")
+            .unwrap()
+            .get_document("properties")
+            .unwrap();
+        assert_eq!(
+            properties.get_document("dateTimeOption").unwrap(),
+            &bson::doc! { "bsonType": ["null", "date"] }
+        );
+        assert_eq!(
+            properties.get_document("value").unwrap(),
+            &bson::doc! { "bsonType": "int" }
+        );
+        assert_eq!(
+            properties.get_document("strVec").unwrap(),
+            &bson::doc! { "bsonType": "array", "items": { "bsonType": "string" } }
+        );
+        assert_eq!(
+            properties
+                .get_document("twoVec")
+                .unwrap()
+                .get_document("items")
+                .unwrap(),
+            &Two::mongo_validator()
+                .get_document("$jsonSchema")
+                .unwrap()
+                .clone()
+        );
+    }
+
+    #[derive(Serialize, ToJson)]
+    #[json(mongo)]
+    #[json(index(fields(tenant_id, created_at = "desc"), unique, name = "tenant_recent"))]
+    #[allow(dead_code)]
+    struct Session {
+        #[serde(rename = "_id")]
+        id: ObjectId,
+        #[json(index)]
+        tenant_id: String,
+        #[json(index(ttl = 3600, order = "desc"))]
+        created_at: DateTime,
+    }
+
+    #[test]
+    fn test_index_models() {
+        let models = Session::index_models();
+        assert_eq!(models.len(), 3);
+
+        assert_eq!(
+            models[0].keys,
+            bson::doc! { "tenant_id": 1, "created_at": -1 }
+        );
+        let options = models[0].options.as_ref().unwrap();
+        assert_eq!(options.unique, Some(true));
+        assert_eq!(options.name.as_deref(), Some("tenant_recent"));
+
+        assert_eq!(models[1].keys, bson::doc! { "tenant_id": 1 });
+        assert_eq!(models[1].options.as_ref().unwrap().unique, None);
+
+        assert_eq!(models[2].keys, bson::doc! { "created_at": -1 });
+        assert_eq!(
+            models[2].options.as_ref().unwrap().expire_after,
+            Some(std::time::Duration::from_secs(3600))
+        );
+    }
+
+    #[derive(Serialize, ToJson)]
+    #[json(rename_all = "camelCase")]
+    #[json(mongo)]
+    struct Account {
+        #[serde(rename = "_id")]
+        #[json(rename = "id")]
+        id: ObjectId,
+        display_name: String,
+        #[serde(rename = "mail")]
+        email_address: Option<String>,
+    }
+
+    #[test]
+    fn test_json_rename() {
+        let oid = ObjectId::new();
+        let account = Account {
+            id: oid,
+            display_name: "Ada".to_string(),
+            email_address: None,
+        };
+
+        let json = account.to_json();
+        assert_eq!(
+            json,
+            json!({ "id": oid.to_hex(), "displayName": "Ada", "mail": null })
+        );
+        assert_eq!(Account::from_json(&json).unwrap().display_name, "Ada");
+        assert_eq!(Account::FIELD_ID, "id");
+
+        // Stored names still come from serde.
+        let stored = bson::to_document(&account).unwrap();
+        assert!(stored.contains_key("_id") && stored.contains_key("mail"));
+        assert_eq!(
+            Account::mongo_projection_for(&["id", "mail"]).unwrap(),
+            bson::doc! { "_id": 1, "mail": 1 }
+        );
+        assert_eq!(Account::paths().email_address(), "mail");
+    }
+
+    #[derive(ToJson)]
+    #[json(omit_none)]
+    struct MobileProfile {
+        name: String,
+        nickname: Option<String>,
+        #[json(default_for_none = "UTC")]
+        timezone: Option<String>,
+        #[json(omit_empty)]
+        tags: Vec<String>,
+        #[json(omit_empty)]
+        bio: Option<String>,
+    }
+
+    #[test]
+    fn test_null_and_empty_policies() {
+        let mut profile = MobileProfile {
+            name: "Ada".to_string(),
+            nickname: None,
+            timezone: None,
+            tags: vec![],
+            bio: Some(String::new()),
+        };
+        assert_eq!(
+            profile.to_json(),
+            json!({ "name": "Ada", "timezone": "UTC" })
+        );
+
+        let read = MobileProfile::from_json(&profile.to_json()).unwrap();
+        assert!(read.nickname.is_none() && read.tags.is_empty() && read.bio.is_none());
+
+        let old = MobileProfile::from_json(&profile.to_json()).unwrap();
+        profile.nickname = Some("ada".to_string());
+        profile.timezone = Some("Europe/London".to_string());
+        profile.tags = vec!["admin".to_string()];
+        assert_eq!(
+            profile.to_json(),
+            json!({
+                "name": "Ada",
+                "nickname": "ada",
+                "timezone": "Europe/London",
+                "tags": ["admin"],
+            })
+        );
+        assert_eq!(
+            old.json_patch(&profile),
+            json!([
+                { "op": "add", "path": "/nickname", "value": "ada" },
+                { "op": "replace", "path": "/timezone", "value": "Europe/London" },
+                { "op": "add", "path": "/tags", "value": ["admin"] },
+            ])
+        );
+
+        profile.apply_merge_patch(&json!({ "tags": null })).unwrap();
+        assert!(profile.tags.is_empty());
+    }
+
+    #[derive(ToJson)]
+    #[json(rename_all = "camelCase")]
+    #[json(computed(name = "fullName", with = "Self::full_name", after = "last_name"))]
+    #[json(computed(name = "isExpired", with = "Self::is_expired"))]
+    struct Member {
+        first_name: String,
+        last_name: String,
+        expires_in: i64,
+    }
+
+    impl Member {
+        fn full_name(&self) -> String {
+            format!("{} {}", self.first_name, self.last_name)
+        }
+        fn is_expired(&self) -> bool {
+            self.expires_in <= 0
+        }
+    }
+
+    #[test]
+    fn test_computed_fields() {
+        let mut member = Member {
+            first_name: "Ada".to_string(),
+            last_name: "Lovelace".to_string(),
+            expires_in: 10,
+        };
+        assert_eq!(
+            member.to_json_string(),
+            r#"{"firstName":"Ada","lastName":"Lovelace","fullName":"Ada Lovelace","expiresIn":10,"isExpired":false}"#
+        );
+
+        let old = Member::from_json(&member.to_json()).unwrap();
+        member.expires_in = 0;
+        assert_eq!(
+            old.json_patch(&member),
+            json!([
+                { "op": "replace", "path": "/expiresIn", "value": 0 },
+                { "op": "replace", "path": "/isExpired", "value": true },
+            ])
+        );
+
+        let mut read = Member::from_json(&member.to_json()).unwrap();
+        read.apply_merge_patch(&old.to_json()).unwrap();
+        assert_eq!(read.to_json(), old.to_json());
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, ToJson, Serialize)]
+    #[json(serialize, repr = "int")]
+    enum Level {
+        Low = 1,
+        High = 2,
+    }
+
+    #[derive(ToJson, Serialize)]
+    #[json(serialize, omit_none, rename_all = "camelCase")]
+    #[json(computed(name = "isHigh", with = "Self::is_high", before = "level"))]
+    struct Alert {
+        id: ObjectId,
+        raised_at: DateTime,
+        level: Level,
+        note: Option<String>,
+        tags: Vec<String>,
+    }
+
+    impl Alert {
+        fn is_high(&self) -> bool {
+            self.level == Level::High
+        }
+    }
+
+    #[test]
+    fn test_serialize_matches_to_json() {
+        let alert = Alert {
+            id: ObjectId::new(),
+            raised_at: DateTime::now(),
+            level: Level::High,
+            note: None,
+            tags: vec!["disk".to_string()],
+        };
+
+        assert_eq!(
+            serde_json::to_string(&alert.as_json()).unwrap(),
+            alert.to_json_string()
+        );
+        assert_eq!(
+            serde_json::to_value(alert.as_json()).unwrap(),
+            alert.to_json()
+        );
+        assert_eq!(
+            serde_json::to_value(Level::Low.as_json()).unwrap(),
+            json!(1)
+        );
+
+        // The derived `Serialize`, which the mongodb driver stores, is untouched.
+        let stored = bson::to_document(&alert).unwrap();
+        assert_eq!(stored.get_object_id("id").unwrap(), alert.id);
+        assert_eq!(stored.get_str("level").unwrap(), "High");
+    }
+
+    mod framework {
+        pub use to_json as json;
+    }
+
+    #[derive(framework::json::ToJson)]
+    #[json(crate = "framework::json", serialize, patch, mongo)]
+    struct Reexported {
+        id: ObjectId,
+        tags: Vec<String>,
+        note: Option<String>,
+    }
+
+    #[test]
+    fn test_crate_path() {
+        let value = Reexported {
+            id: ObjectId::new(),
+            tags: vec!["a".to_string()],
+            note: None,
+        };
+        let json: framework::json::serde_json::Value = value.to_json();
+        assert_eq!(json["tags"], json!(["a"]));
+        assert_eq!(serde_json::to_value(value.as_json()).unwrap(), json);
+        assert_eq!(Reexported::paths().tags().as_str(), "tags");
+    }
+
+    #[derive(ToJson)]
+    #[json(type_tag = "__type", serialize)]
+    struct UserCreated {
+        name: String,
+        #[json]
+        address: Address,
+    }
+
+    #[derive(ToJson)]
+    #[json(type_tag(key = "kind", value = "address"))]
+    #[json(envelope(key = "data", siblings(version = 2)))]
+    struct Address {
+        city: String,
+    }
+
+    #[test]
+    fn test_type_tag_and_envelope() {
+        let mut event = UserCreated {
+            name: "Ada".to_string(),
+            address: Address {
+                city: "London".to_string(),
+            },
+        };
+        let json = json!({
+            "__type": "UserCreated",
+            "name": "Ada",
+            "address": { "data": { "kind": "address", "city": "London" }, "version": 2 },
+        });
+        assert_eq!(event.to_json(), json);
+        assert_eq!(serde_json::to_value(event.as_json()).unwrap(), json);
+
+        let read = UserCreated::from_json(&json).unwrap();
+        assert_eq!(read.address.city, "London");
+        assert_eq!(
+            UserCreated::from_json(&json!({ "__type": "Other", "name": "Ada", "address": {} }))
+                .err()
+                .unwrap()
+                .to_string(),
+            "/__type: expected `UserCreated`"
+        );
+
+        let old = UserCreated::from_json(&json).unwrap();
+        event
+            .apply_merge_patch(&json!({ "address": { "data": { "city": "Paris" } } }))
+            .unwrap();
+        assert_eq!(event.address.city, "Paris");
+        assert_eq!(
+            old.json_patch(&event),
+            json!([{ "op": "replace", "path": "/address/data/city", "value": "Paris" }])
+        );
+    }
+
+    #[derive(ToJson)]
+    #[json(mongo)]
+    struct Category {
+        #[json(reference)]
+        id: ObjectId,
+        name: String,
+        #[json]
+        children: Vec<Category>,
+        #[json]
+        parent: Option<Box<Category>>,
+    }
+
+    #[test]
+    fn test_recursive_types() {
+        let leaf_id = ObjectId::new();
+        let root = Category {
+            id: ObjectId::new(),
+            name: "root".to_string(),
+            children: vec![Category {
+                id: ObjectId::new(),
+                name: "child".to_string(),
+                children: vec![Category {
+                    id: leaf_id,
+                    name: "leaf".to_string(),
+                    children: vec![],
+                    parent: None,
+                }],
+                parent: None,
+            }],
+            parent: None,
+        };
+
+        let json = root.to_json();
+        assert_eq!(json["children"][0]["children"][0]["name"], "leaf");
+        assert_eq!(root.to_json_with_depth(usize::MAX), json);
+
+        let limited = root.to_json_with_depth(1);
+        assert_eq!(limited["children"][0]["name"], "child");
+        assert_eq!(
+            limited["children"][0]["children"][0],
+            json!(leaf_id.to_hex())
+        );
+
+        let mut read = Category::from_json(&json).unwrap();
+        read.apply_merge_patch(&json!({ "parent": { "id": leaf_id.to_hex(), "name": "up", "children": [], "parent": null } }))
+            .unwrap();
+        assert_eq!(read.parent.as_ref().unwrap().name, "up");
+        assert_eq!(read.to_json()["parent"]["name"], "up");
+
+        let schema = Category::mongo_validator();
+        let properties = schema
+            .get_document("$jsonSchema")
+            .unwrap()
+            .get_document("properties")
+            .unwrap();
+        assert_eq!(
+            properties.get_document("children").unwrap(),
+            &bson::doc! { "bsonType": "array", "items": { "bsonType": "object" } }
+        );
+    }
+
+    #[derive(ToJson)]
+    #[json(serialize)]
+    struct Fragment {
+        extra: serde_json::Value,
+        cached: Box<serde_json::value::RawValue>,
+        #[json(raw)]
+        rendered: String,
+        meta: bson::Document,
+        tag: Option<bson::Bson>,
+    }
+
+    #[test]
+    fn test_passthrough_fields() {
+        let owner = ObjectId::new();
+        let seen = DateTime::from_millis(0);
+        let fragment = Fragment {
+            extra: json!({ "a": [1, 2] }),
+            cached: serde_json::value::RawValue::from_string(r#"{"b":true}"#.to_string()).unwrap(),
+            rendered: r#"[1,"x"]"#.to_string(),
+            meta: bson::doc! { "owner": owner, "seen": seen, "tags": [{ "id": owner }], "count": 3_i64 },
+            tag: Some(bson::Bson::String("t".to_string())),
+        };
+
+        let json = fragment.to_json();
+        assert_eq!(json["extra"], json!({ "a": [1, 2] }));
+        assert_eq!(json["cached"], json!({ "b": true }));
+        assert_eq!(json["rendered"], json!([1, "x"]));
+        assert_eq!(
+            json["meta"],
+            json!({
+                "owner": owner.to_hex(),
+                "seen": "1970-01-01T00:00:00Z",
+                "tags": [{ "id": owner.to_hex() }],
+                "count": 3,
+            })
+        );
+        assert_eq!(json["tag"], "t");
+        assert_eq!(serde_json::to_value(fragment.as_json()).unwrap(), json);
+
+        let read = Fragment::from_json(&json).unwrap();
+        assert_eq!(read.cached.get(), r#"{"b":true}"#);
+        assert_eq!(read.rendered, r#"[1,"x"]"#);
+        assert_eq!(read.meta.get_str("owner").unwrap(), owner.to_hex());
+        assert_eq!(read.to_json(), json);
+
+        let invalid = Fragment {
+            rendered: "not json".to_string(),
+            ..read
+        };
+        assert_eq!(invalid.to_json()["rendered"], "not json");
+        let read = Fragment::from_json(&invalid.to_json()).unwrap();
+        assert_eq!(read.rendered, "not json");
+    }
+
+    #[derive(ToJson)]
+    struct Owner {
+        #[json(rename = "_id")]
+        id: ObjectId,
+        name: String,
+    }
+
+    #[derive(ToJson)]
+    #[json(serialize, mongo)]
+    struct Listing {
+        owner: Owner,
+        co_owners: Vec<Owner>,
+        previous: Option<Owner>,
+        labels: Vec<String>,
+    }
+
+    #[test]
+    fn test_nested_without_marker() {
+        let owner_id = ObjectId::new();
+        let owner = |name: &str| Owner {
+            id: owner_id,
+            name: name.to_string(),
+        };
+        let listing = Listing {
+            owner: owner("Ana"),
+            co_owners: vec![owner("Bo")],
+            previous: None,
+            labels: vec!["new".to_string()],
+        };
+
+        let json = listing.to_json();
+        assert_eq!(
+            json["owner"],
+            json!({ "_id": owner_id.to_hex(), "name": "Ana" })
+        );
+        assert_eq!(json["co_owners"][0]["_id"], owner_id.to_hex());
+        assert_eq!(json["previous"], json!(null));
+        assert_eq!(json["labels"], json!(["new"]));
+        assert_eq!(serde_json::to_value(listing.as_json()).unwrap(), json);
+
+        let mut read = Listing::from_json(&json).unwrap();
+        read.apply_merge_patch(&json!({ "owner": { "name": "Cy" }, "previous": { "_id": owner_id.to_hex(), "name": "Di" } }))
+            .unwrap();
+        assert_eq!(read.owner.name, "Cy");
+        assert_eq!(read.owner.id, owner_id);
+        assert_eq!(read.previous.as_ref().unwrap().name, "Di");
+
+        let paths: Vec<String> = listing
+            .json_diff(&read)
+            .into_iter()
+            .map(|change| change.path)
+            .collect();
+        assert_eq!(paths, ["/owner/name", "/previous"]);
+
+        assert_eq!(
+            Listing::mongo_projection_for(&["owner._id"]).unwrap(),
+            bson::doc! { "owner.id": 1 }
+        );
+        let schema = Listing::mongo_validator();
+        let properties = schema
+            .get_document("$jsonSchema")
+            .unwrap()
+            .get_document("properties")
+            .unwrap();
+        assert_eq!(
+            properties
+                .get_document("owner")
+                .unwrap()
+                .get_str("bsonType")
+                .unwrap(),
+            "object"
+        );
+    }
+
+    struct Viewer {
+        admin: bool,
+    }
+
+    impl Viewer {
+        fn can_see_email(&self) -> bool {
+            self.admin
+        }
+    }
+
+    impl to_json::JsonContext for Viewer {
+        fn include_field(&self, type_name: &str, key: &str) -> bool {
+            self.admin || (type_name, key) != ("Contact", "notes")
+        }
+    }
+
+    #[derive(ToJson)]
+    #[json(context = "Viewer")]
+    struct Contact {
+        name: String,
+        #[json(if = "ctx.can_see_email()")]
+        email: String,
+        /// Only admins see these.
+        /// Free text.
+        notes: String,
+    }
+
+    #[derive(ToJson)]
+    struct Directory {
+        #[json]
+        owner: Contact,
+        #[json]
+        contacts: Vec<Contact>,
+        categories: Vec<Category>,
+    }
+
+    #[test]
+    fn test_to_json_with_context() {
+        let contact = |name: &str| Contact {
+            name: name.to_string(),
+            email: format!("{}@example.com", name),
+            notes: "vip".to_string(),
+        };
+        let directory = Directory {
+            owner: contact("ana"),
+            contacts: vec![contact("bo")],
+            categories: vec![],
+        };
+
+        let guest = directory.to_json_with(&Viewer { admin: false });
+        assert_eq!(guest["owner"], json!({ "name": "ana" }));
+        assert_eq!(guest["contacts"], json!([{ "name": "bo" }]));
+
+        let admin = directory.to_json_with(&Viewer { admin: true });
+        assert_eq!(admin, directory.to_json());
+        assert_eq!(admin["owner"]["email"], "ana@example.com");
+
+        let category = Category {
+            id: ObjectId::new(),
+            name: "root".to_string(),
+            children: vec![],
+            parent: None,
+        };
+        assert_eq!(category.to_json_with(&()), category.to_json());
+    }
+
+    #[derive(ToJson)]
+    struct Author {
+        #[json(rename_in(v1 = "fullName"))]
+        name: String,
+        #[json(since = 2)]
+        handle: String,
+    }
+
+    #[derive(ToJson)]
+    struct Article {
+        #[json(rename_in(v1 = "headline"))]
+        title: String,
+        #[json(until = 1)]
+        body_html: String,
+        #[json(since = 2)]
+        body: String,
+        #[json]
+        author: Author,
+    }
+
+    #[test]
+    fn test_versioned_output() {
+        let article = Article {
+            title: "Hi".to_string(),
+            body_html: "<p>Hi</p>".to_string(),
+            body: "Hi".to_string(),
+            author: Author {
+                name: "Ana".to_string(),
+                handle: "ana".to_string(),
+            },
+        };
+
+        assert_eq!(
+            article.to_json_version(1),
+            json!({
+                "headline": "Hi",
+                "body_html": "<p>Hi</p>",
+                "author": { "fullName": "Ana" },
+            })
+        );
+        assert_eq!(
+            article.to_json_version(2),
+            json!({
+                "title": "Hi",
+                "body": "Hi",
+                "author": { "name": "Ana", "handle": "ana" },
+            })
+        );
+        assert_eq!(article.to_json()["body_html"], "<p>Hi</p>");
+    }
+
+    #[test]
+    fn test_field_info() {
+        let fields = MyStruct::fields();
+        assert_eq!(fields.len(), MyStruct::mongo_projection().len());
+        let id = &fields[0];
+        assert_eq!((id.name, id.key, id.bson_name), ("_id", "_id", "_id"));
+        assert_eq!(id.type_name, "ObjectId");
+
+        let date_time = fields
+            .iter()
+            .find(|field| field.name == "date_time")
+            .unwrap();
+        assert_eq!(date_time.key, MyStruct::FIELD_DATE_TIME);
+
+        let account = Account::fields();
+        let email = account
+            .iter()
+            .find(|field| field.name == "email_address")
+            .unwrap();
+        assert_eq!((email.key, email.bson_name), ("mail", "mail"));
+
+        let category = Category::fields();
+        let parent = category
+            .iter()
+            .find(|field| field.name == "parent")
+            .unwrap();
+        assert_eq!(parent.type_name, "Option<Box<Category>>");
+        assert!(parent.is_option && parent.nested && !parent.is_vec);
+        let children = category
+            .iter()
+            .find(|field| field.name == "children")
+            .unwrap();
+        assert!(children.is_vec && !children.is_option);
+
+        let notes = Contact::fields()
+            .iter()
+            .find(|field| field.name == "notes")
+            .unwrap();
+        assert_eq!(notes.docs, "Only admins see these.\nFree text.");
+
+        let shapes = Shapes::fields();
+        let type_names: Vec<_> = shapes.iter().map(|field| field.type_name).collect();
+        assert_eq!(
+            type_names,
+            [
+                "[u8; 4]",
+                "(u8, String)",
+                "Vec<Option<Two>>",
+                "RegisterStatus"
+            ]
+        );
+        let nested: Vec<_> = shapes.iter().map(|field| field.nested).collect();
+        assert_eq!(nested, [false, false, true, true]);
+    }
+
+    #[derive(ToJson, Serialize)]
+    #[allow(dead_code)]
+    struct Shapes {
+        bytes: [u8; 4],
+        pair: (u8, String),
+        twos: Vec<Option<Two>>,
+        status: RegisterStatus,
+    }
+
+    #[test]
+    fn test_json_pointer_get_and_set() {
+        let two = |id: ObjectId, status: &str| json!({ "_id": id.to_hex(), "testId2": null, "registerStatus": status, "registerStatus2": null });
+        let (first, second) = (ObjectId::new(), ObjectId::new());
+        let mut my_struct = MyStruct::from_json(&json!({
+            "_id": ObjectId::new().to_hex(),
+            "testId": null,
+            "testId2": null,
+            "name": "test",
+            "value": 1,
+            "dateTime": "2024-01-01T00:00:00Z",
+            "dateTimeOption": null,
+            "two": two(first, "pending"),
+            "twoOpt": null,
+            "twoVec": [two(first, "pending"), two(second, "pending")],
+            "strVec": ["a"],
+            "vecObjectId": [],
+        }))
+        .unwrap();
+
+        assert_eq!(my_struct.json_get("/two/_id"), Some(json!(first.to_hex())));
+        assert_eq!(
+            my_struct.json_get("/twoVec/1/registerStatus"),
+            Some(json!("pending"))
+        );
+        assert_eq!(my_struct.json_get("/strVec/0"), Some(json!("a")));
+        assert_eq!(my_struct.json_get("/twoOpt/_id"), None);
+        assert_eq!(my_struct.json_get("/nope"), None);
+        assert_eq!(my_struct.json_get(""), Some(my_struct.to_json()));
+
+        my_struct
+            .json_set("/twoVec/1/registerStatus", json!("complete"))
+            .unwrap();
+        assert!(matches!(
+            my_struct.two_vec[1].register_status,
+            Some(RegisterStatus::Complete)
+        ));
+        my_struct
+            .json_set("/two/_id", json!(second.to_hex()))
+            .unwrap();
+        assert_eq!(my_struct.two.id, second);
+        my_struct
+            .json_set("/dateTime", json!("2025-06-01T12:00:00Z"))
+            .unwrap();
+        assert_eq!(
+            my_struct.date_time,
+            DateTime::parse_rfc3339_str("2025-06-01T12:00:00Z").unwrap()
+        );
+        my_struct.json_set("/strVec/0", json!("b")).unwrap();
+        assert_eq!(my_struct.str_vec, ["b"]);
+
+        let err = my_struct.json_set("/two/_id", json!("bad")).unwrap_err();
+        assert_eq!(err.path, "/two/_id");
+        let err = my_struct
+            .json_set("/twoVec/5/registerStatus", json!("complete"))
+            .unwrap_err();
+        assert_eq!(err.path, "/twoVec/5");
+        assert!(my_struct.json_set("/twoOpt/_id", json!(null)).is_err());
+        assert!(my_struct.json_set("/nope", json!(1)).is_err());
+
+        // RFC 6901 array indexes are plain digits without leading zeros.
+        assert_eq!(my_struct.json_get("/twoVec/01/_id"), None);
+        assert_eq!(my_struct.json_get("/twoVec/+1/_id"), None);
+        assert!(my_struct.json_set("/strVec/00", json!("c")).is_err());
+        assert!(my_struct.json_set("/strVec/+0", json!("c")).is_err());
+        assert_eq!(my_struct.str_vec, ["b"]);
+    }
+
+    #[test]
+    fn test_json_pointer_set_policies() {
+        let mut fragment = Fragment::from_json(&json!({
+            "extra": { "a": 1 },
+            "cached": { "b": true },
+            "rendered": "[]",
+            "meta": {},
+            "tag": null,
+        }))
+        .unwrap();
+        fragment.json_set("/extra/newKey", json!(2)).unwrap();
+        assert_eq!(fragment.extra, json!({ "a": 1, "newKey": 2 }));
+        assert!(fragment.json_set("/extra/a/b", json!(3)).is_err());
+
+        let mut profile =
+            MobileProfile::from_json(&json!({ "name": "Ada", "tags": ["x"] })).unwrap();
+        profile.json_set("/tags", json!(null)).unwrap();
+        assert!(profile.tags.is_empty());
+        assert_eq!(profile.json_get("/tags"), None);
+    }
+
+    #[test]
+    fn test_from_json_round_trip() {
+        let test = Test {
+            id: ObjectId::new(),
+            name: "test".to_string(),
+            created_at: DateTime::from_millis(0),
+            updated_at: DateTime::from_millis(1_700_000_000_123),
+        };
+
+        let json = test.to_json();
+        assert_eq!(json["createdAt"], "1970-01-01T00:00:00Z");
+        assert_eq!(Test::from_json(&json).unwrap().to_json(), json);
+    }
+
+    #[derive(ToJson, Serialize, Clone, Copy)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    #[allow(dead_code)]
+    enum Shipping {
+        #[json(value = "std")]
+        Standard,
+        NextDay,
+    }
+
+    #[derive(ToJson, Serialize)]
+    #[json(mongo)]
+    #[allow(dead_code)]
+    struct Parcel {
+        #[json]
+        shipping: Shipping,
+        #[json]
+        level: Option<Level>,
+    }
+
+    #[test]
+    fn test_enum_validator_values() {
+        assert_eq!(Shipping::Standard.to_json(), json!("std"));
+        let validator = Parcel::mongo_validator();
+        let properties = validator
+            .get_document("$jsonSchema")
+            .unwrap()
+            .get_document("properties")
+            .unwrap();
+        assert_eq!(
+            properties.get_document("shipping").unwrap(),
+            &bson::doc! { "enum": ["STANDARD", "NEXT_DAY"] }
+        );
+        assert_eq!(
+            properties.get_document("level").unwrap(),
+            &bson::doc! { "enum": ["Low", "High", null] }
+        );
+    }
+
+    // A type of the user's own that shares its name with `bson::Document`.
+    #[derive(ToJson, Clone)]
+    struct Document {
+        title: String,
+    }
+
+    #[derive(ToJson)]
+    struct Folder {
+        document: Document,
+        documents: Vec<Document>,
+    }
+
+    #[test]
+    fn test_unqualified_document_is_nested() {
+        let document = Document {
+            title: "notes".to_string(),
+        };
+        let folder = Folder {
+            documents: vec![document.clone()],
+            document,
+        };
+        let json = folder.to_json();
+        assert_eq!(
+            json,
+            json!({ "document": { "title": "notes" }, "documents": [{ "title": "notes" }] })
+        );
+        assert_eq!(Folder::from_json(&json).unwrap().document.title, "notes");
+    }
+
+    mod imported {
+        use bson::{oid::ObjectId, Bson, Document};
+        use serde::Serialize;
+        use serde_json::json;
+        use to_json_macro::ToJson;
+
+        #[derive(ToJson, Serialize)]
+        #[json(mongo)]
+        struct Settings {
+            meta: Document,
+            values: Vec<Bson>,
+        }
+
+        #[test]
+        fn test_imported_bson_types() {
+            let id = ObjectId::new();
+            let settings = Settings {
+                meta: bson::doc! { "owner": id },
+                values: vec![Bson::ObjectId(id)],
+            };
+
+            let json = settings.to_json();
+            assert_eq!(
+                json,
+                json!({ "meta": { "owner": id.to_hex() }, "values": [id.to_hex()] })
+            );
+            let read = Settings::from_json(&json).unwrap();
+            assert_eq!(read.meta.get_str("owner").unwrap(), id.to_hex());
+
+            let validator = Settings::mongo_validator();
+            let properties = validator
+                .get_document("$jsonSchema")
+                .unwrap()
+                .get_document("properties")
+                .unwrap();
+            assert_eq!(
+                properties.get_document("meta").unwrap(),
+                &bson::doc! { "bsonType": "object" }
+            );
+        }
+    }
+
+    #[derive(ToJson, Serialize)]
+    #[json(rename_all = "camelCase", mongo)]
+    struct Login {
+        #[serde(rename = "_id")]
+        _id: ObjectId,
+        user_agent: String,
+    }
+
+    #[test]
+    fn test_serde_rename_beats_json_rename_all() {
+        let login = Login {
+            _id: ObjectId::new(),
+            user_agent: "curl".to_string(),
+        };
+        assert_eq!(
+            login.to_json(),
+            json!({ "_id": login._id.to_hex(), "userAgent": "curl" })
+        );
+        assert_eq!(
+            Login::mongo_projection_for(&["_id", "userAgent"]).unwrap(),
+            bson::doc! { "_id": 1, "user_agent": 1 }
+        );
+    }
+
+    #[derive(ToJson, Serialize)]
+    #[json(patch, omit_empty, envelope(key = "data"))]
+    struct Note {
+        title: String,
+        labels: Vec<String>,
+    }
+
+    #[test]
+    fn test_patch_reads_like_merge_patch() {
+        let input = json!({ "data": { "title": "Todo", "labels": null } });
+
+        let patch = NotePatch::from_json(&input).unwrap();
+        assert_eq!(patch.title, to_json::PatchField::Value("Todo".to_string()));
+        assert_eq!(patch.labels, to_json::PatchField::Value(vec![]));
+
+        let mut note = Note {
+            title: "Draft".to_string(),
+            labels: vec!["x".to_string()],
+        };
+        note.apply_merge_patch(&input).unwrap();
+        let mut patched = Note {
+            title: "Draft".to_string(),
+            labels: vec!["x".to_string()],
+        };
+        patch.apply_to(&mut patched);
+        assert_eq!(patched.to_json(), note.to_json());
+
+        let err = NotePatch::from_json(&json!({ "data": { "title": 1 } }))
+            .err()
+            .unwrap();
+        assert_eq!(err.path, "/data/title");
+        assert!(NotePatch::from_json(&json!({})).unwrap().title.is_absent());
+    }
 }
@@ -0,0 +1,71 @@
+/// Passed to `to_json_with`, for output that depends on who asks for it.
+///
+/// Fields marked `#[json(if = "...")]` are written only when their condition,
+/// which can use `ctx`, holds. Give the struct `#[json(context = "MyContext")]`
+/// for conditions that call methods of your own context type.
+///
+/// Such a struct only renders with that context, so a struct holding it has to
+/// mark the field `#[json]` to take the same one:
+///
+/// ```
+/// use to_json::{JsonContext, ToJson};
+///
+/// struct Admin;
+///
+/// impl JsonContext for Admin {}
+///
+/// impl Admin {
+///     fn is_admin(&self) -> bool {
+///         true
+///     }
+/// }
+///
+/// #[derive(ToJson)]
+/// #[json(context = "Admin")]
+/// struct User {
+///     #[json(if = "ctx.is_admin()")]
+///     email: String,
+/// }
+///
+/// #[derive(ToJson)]
+/// struct Team {
+///     #[json]
+///     users: Vec<User>,
+/// }
+///
+/// let team = Team { users: vec![User { email: "a@example.com".to_string() }] };
+/// assert_eq!(team.to_json_with(&Admin)["users"][0]["email"], "a@example.com");
+/// ```
+///
+/// Left unmarked, the field is rejected with a note saying so:
+///
+/// ```compile_fail
+/// # use to_json::{JsonContext, ToJson};
+/// # struct Admin;
+/// # impl JsonContext for Admin {}
+/// #[derive(ToJson)]
+/// #[json(context = "Admin")]
+/// struct User {
+///     email: String,
+/// }
+///
+/// #[derive(ToJson)]
+/// struct Team {
+///     users: Vec<User>,
+/// }
+/// ```
+///
+/// Only `to_json_with` checks the condition. `to_json`, `Display`, the
+/// `as_json` adapter of `#[json(serialize)]` and `json_get` have no context and
+/// still write the field, so don't hand their output to callers who must not
+/// see it.
+pub trait JsonContext {
+    /// Whether `key` of the type named `type_name` is written at all.
+    fn include_field(&self, type_name: &str, key: &str) -> bool {
+        let _ = (type_name, key);
+        true
+    }
+}
+
+/// A context that lets every key through.
+impl JsonContext for () {}
@@ -0,0 +1,53 @@
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::JsonError;
+
+// Generated code reads plain fields back through serde, but must keep compiling
+// for field types that only implement `Serialize`. Calling
+// `(&FromJsonValue::<T>::new(..)).deserialize_value()` resolves to
+// `DeserializeValue` when `T: DeserializeOwned` and to `NotDeserializable`
+// otherwise.
+pub struct FromJsonValue<'a, T> {
+    value: &'a Value,
+    path: &'a str,
+    marker: PhantomData<T>,
+}
+
+impl<'a, T> FromJsonValue<'a, T> {
+    pub fn new(value: &'a Value, path: &'a str) -> Self {
+        FromJsonValue {
+            value,
+            path,
+            marker: PhantomData,
+        }
+    }
+}
+
+pub trait DeserializeValue<T> {
+    fn deserialize_value(&self) -> Result<T, JsonError>;
+}
+
+impl<T: DeserializeOwned> DeserializeValue<T> for FromJsonValue<'_, T> {
+    fn deserialize_value(&self) -> Result<T, JsonError> {
+        serde_json::from_value(self.value.clone()).map_err(|err| JsonError::new(self.path, err))
+    }
+}
+
+pub trait NotDeserializable<T> {
+    fn deserialize_value(&self) -> Result<T, JsonError>;
+}
+
+impl<T> NotDeserializable<T> for &FromJsonValue<'_, T> {
+    fn deserialize_value(&self) -> Result<T, JsonError> {
+        Err(JsonError::new(
+            self.path,
+            format!(
+                "{} does not implement Deserialize",
+                std::any::type_name::<T>()
+            ),
+        ))
+    }
+}
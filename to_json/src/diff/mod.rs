@@ -0,0 +1,49 @@
+use serde_json::{json, Value};
+
+/// One difference found by a generated `json_diff`.
+///
+/// `path` is a JSON Pointer into the `to_json` output. `old` or `new` is
+/// `None` when the key is missing on that side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub path: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+impl Change {
+    /// The RFC 6902 operation that applies this change.
+    pub fn to_patch_operation(&self) -> Value {
+        match (&self.old, &self.new) {
+            (None, Some(new)) => json!({ "op": "add", "path": self.path, "value": new }),
+            (Some(_), None) => json!({ "op": "remove", "path": self.path }),
+            (_, new) => json!({ "op": "replace", "path": self.path, "value": new }),
+        }
+    }
+}
+
+/// Builds an RFC 6902 JSON Patch document out of `changes`.
+pub fn json_patch(changes: &[Change]) -> Value {
+    Value::Array(changes.iter().map(Change::to_patch_operation).collect())
+}
+
+pub fn push_change(changes: &mut Vec<Change>, path: String, old: Value, new: Value) {
+    if old != new {
+        changes.push(Change {
+            path,
+            old: Some(old),
+            new: Some(new),
+        });
+    }
+}
+
+pub fn push_optional_change(
+    changes: &mut Vec<Change>,
+    path: String,
+    old: Option<Value>,
+    new: Option<Value>,
+) {
+    if old != new {
+        changes.push(Change { path, old, new });
+    }
+}
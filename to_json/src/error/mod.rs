@@ -0,0 +1,79 @@
+use std::fmt;
+
+/// Returned when a string does not name any variant of a `ToJson` unit enum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseEnumError {
+    pub type_name: &'static str,
+    pub value: String,
+    pub expected: &'static [&'static str],
+}
+
+impl fmt::Display for ParseEnumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid {} `{}`, expected one of: {}",
+            self.type_name,
+            self.value,
+            self.expected.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ParseEnumError {}
+
+/// Returned when JSON cannot be turned back into a `ToJson` type.
+///
+/// `path` is the JSON Pointer of the offending value, empty for the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    pub path: String,
+    pub message: String,
+}
+
+impl JsonError {
+    pub fn new(path: &str, message: impl fmt::Display) -> Self {
+        JsonError {
+            path: path.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+pub fn expect_str<'a>(value: &'a serde_json::Value, path: &str) -> Result<&'a str, JsonError> {
+    value
+        .as_str()
+        .ok_or_else(|| JsonError::new(path, "expected a string"))
+}
+
+pub fn expect_object<'a>(
+    value: &'a serde_json::Value,
+    path: &str,
+) -> Result<&'a serde_json::Map<String, serde_json::Value>, JsonError> {
+    value
+        .as_object()
+        .ok_or_else(|| JsonError::new(path, "expected an object"))
+}
+
+pub fn reject_unknown_keys(
+    object: &serde_json::Map<String, serde_json::Value>,
+    keys: &[&str],
+    path: &str,
+) -> Result<(), JsonError> {
+    match object.keys().find(|key| !keys.contains(&key.as_str())) {
+        Some(key) => Err(JsonError::new(path, format!("unknown field `{}`", key))),
+        None => Ok(()),
+    }
+}
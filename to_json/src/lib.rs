@@ -0,0 +1,62 @@
+//! The `ToJson` derive and everything the code it generates refers to.
+//!
+//! Generated code only goes through this crate, so depending on `to_json`
+//! alone is enough. Crates that re-export it point the derive at their own
+//! path with `#[json(crate = "...")]`.
+
+mod context;
+mod de;
+mod diff;
+mod error;
+mod node;
+mod patch;
+mod paths;
+mod pointer;
+mod query;
+mod scalar;
+mod ser;
+mod value;
+
+pub use to_json_macro::ToJson;
+
+pub use indexmap;
+pub use mongodb;
+pub use mongodb::bson;
+pub use serde;
+pub use serde_json;
+
+#[cfg(feature = "chrono")]
+pub use chrono;
+#[cfg(feature = "rust_decimal")]
+pub use rust_decimal;
+#[cfg(feature = "time")]
+pub use time;
+#[cfg(feature = "url")]
+pub use url;
+#[cfg(feature = "uuid")]
+pub use uuid;
+
+pub use context::JsonContext;
+pub use diff::{json_patch, Change};
+pub use error::{JsonError, ParseEnumError};
+pub use patch::PatchField;
+pub use paths::{FieldInfo, FieldPath, ToJsonFields, ToJsonPaths};
+pub use pointer::JsonPointer;
+pub use query::{ArrayFilter, FieldFilter, Filter, Sort, SortField, ToJsonFilter, ToJsonSort};
+pub use ser::AsJson;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::de::{DeserializeValue, FromJsonValue, NotDeserializable};
+    pub use crate::diff::{push_change, push_optional_change};
+    pub use crate::error::{expect_object, expect_str, reject_unknown_keys};
+    pub use crate::node::{
+        nested_value, JsonNode, JsonNodeWith, NodeOps, PlainOps, Probe, ProbeConsts, Render,
+        ScalarOps,
+    };
+    pub use crate::paths::Selectable;
+    pub use crate::query::{nullable_schema, recursive_schema};
+    pub use crate::scalar::JsonScalar;
+    pub use crate::ser::SerializeJson;
+    pub use crate::value::{array_index, is_empty_value, pointer_set, split_pointer};
+}
@@ -0,0 +1,409 @@
+use std::marker::PhantomData;
+
+use mongodb::bson::Document;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::diff::push_change;
+use crate::paths::Selectable;
+use crate::scalar::JsonScalar;
+use crate::{Change, JsonError};
+
+/// Implemented by the `ToJson` derive, so that fields holding such a type
+/// are expanded like `#[json]` fields even when the attribute is left off.
+pub trait JsonNode: Sized {
+    fn json_value(&self, render: Render) -> Value;
+    fn json_reference(&self) -> Value;
+    fn json_diff_into(&self, other: &Self, path: &str, changes: &mut Vec<Change>);
+    fn from_json_at(value: &Value, path: &str) -> Result<Self, JsonError>;
+    fn apply_merge_patch_at(&mut self, patch: &Value, path: &str) -> Result<(), JsonError>;
+    fn check_merge_patch_at(&self, patch: &Value, path: &str) -> Result<(), JsonError>;
+    fn bson_path(json_path: &str) -> Option<String>;
+    fn bson_schema() -> Document;
+}
+
+/// Which output a `ToJson` type renders: `to_json`, `to_json_with_depth` or
+/// `to_json_version`. They differ only in what happens to nested values and in
+/// which keys a version has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Render {
+    Full,
+    /// Nested values this many levels down are replaced by their reference.
+    Depth(usize),
+    /// The keys of an API version.
+    Version(u32),
+}
+
+impl Render {
+    /// How values one level down are rendered, `None` when only their
+    /// reference is left.
+    pub fn nested(self) -> Option<Render> {
+        match self {
+            Render::Depth(depth) => depth.checked_sub(1).map(Render::Depth),
+            render => Some(render),
+        }
+    }
+
+    pub fn version(self) -> Option<u32> {
+        match self {
+            Render::Version(version) => Some(version),
+            _ => None,
+        }
+    }
+}
+
+/// Renders a value nested in one rendered as `render`.
+pub fn nested_value<T: JsonNode>(value: &T, render: Render) -> Value {
+    match render.nested() {
+        Some(render) => value.json_value(render),
+        None => value.json_reference(),
+    }
+}
+
+/// Implemented by the `ToJson` derive for every context its `to_json_with` takes.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not rendered with the context `{C}`",
+    note = "a type with `#[json(context = \"...\")]` only renders with that context; mark the field holding it `#[json]` so the struct takes the same context"
+)]
+pub trait JsonNodeWith<C: ?Sized> {
+    fn json_value_with(&self, ctx: &C) -> Value;
+}
+
+// Generated code can't tell at expansion time whether a field type derives
+// `ToJson`. Calling `(&&&Probe::<T>::new()).method(..)` resolves to `NodeOps`
+// when `T: JsonNode`, to `ScalarOps` when `T: JsonScalar` and to `PlainOps`,
+// which goes through serde, otherwise.
+pub struct Probe<T>(PhantomData<T>);
+
+impl<T> Probe<T> {
+    pub fn new() -> Self {
+        Probe(PhantomData)
+    }
+
+    // Names `T` through a value, where the type isn't spelled out.
+    pub fn of(_value: &T) -> Self {
+        Probe(PhantomData)
+    }
+}
+
+impl<T> Default for Probe<T> {
+    fn default() -> Self {
+        Probe::new()
+    }
+}
+
+pub trait NodeOps<T> {
+    fn is_node(&self) -> bool;
+    fn to_value(&self, value: &T, render: Render) -> Value;
+    fn to_value_with<C: ?Sized>(&self, value: &T, ctx: &C) -> Value
+    where
+        T: JsonNodeWith<C>;
+    fn serializable(&self, value: &T) -> Value;
+    fn diff(&self, old: &T, new: &T, path: &str, changes: &mut Vec<Change>);
+    fn read_value(
+        &self,
+        value: &Value,
+        path: &str,
+        plain: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<T, JsonError>;
+    fn merge_patch(
+        &self,
+        target: &mut T,
+        patch: &Value,
+        path: &str,
+        replace: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<(), JsonError>;
+    fn check_merge_patch(
+        &self,
+        target: &T,
+        patch: &Value,
+        path: &str,
+        replace: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<(), JsonError>;
+    fn bson_path(&self, rest: &str) -> Option<String>;
+    fn bson_schema(&self, plain: Document) -> Document;
+}
+
+impl<T: JsonNode> NodeOps<T> for &&Probe<T> {
+    fn is_node(&self) -> bool {
+        true
+    }
+
+    fn to_value(&self, value: &T, render: Render) -> Value {
+        nested_value(value, render)
+    }
+
+    fn to_value_with<C: ?Sized>(&self, value: &T, ctx: &C) -> Value
+    where
+        T: JsonNodeWith<C>,
+    {
+        value.json_value_with(ctx)
+    }
+
+    fn serializable(&self, value: &T) -> Value {
+        value.json_value(Render::Full)
+    }
+
+    fn diff(&self, old: &T, new: &T, path: &str, changes: &mut Vec<Change>) {
+        old.json_diff_into(new, path, changes);
+    }
+
+    fn read_value(
+        &self,
+        value: &Value,
+        path: &str,
+        _plain: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<T, JsonError> {
+        T::from_json_at(value, path)
+    }
+
+    fn merge_patch(
+        &self,
+        target: &mut T,
+        patch: &Value,
+        path: &str,
+        _replace: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<(), JsonError> {
+        target.apply_merge_patch_at(patch, path)
+    }
+
+    fn check_merge_patch(
+        &self,
+        target: &T,
+        patch: &Value,
+        path: &str,
+        _replace: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<(), JsonError> {
+        target.check_merge_patch_at(patch, path)
+    }
+
+    fn bson_path(&self, rest: &str) -> Option<String> {
+        T::bson_path(rest)
+    }
+
+    fn bson_schema(&self, _plain: Document) -> Document {
+        T::bson_schema()
+    }
+}
+
+pub trait ScalarOps<T> {
+    fn is_node(&self) -> bool;
+    fn to_value(&self, value: &T, render: Render) -> Value;
+    fn to_value_with<C: ?Sized>(&self, value: &T, ctx: &C) -> Value;
+    fn serializable(&self, value: &T) -> Value;
+    fn diff(&self, old: &T, new: &T, path: &str, changes: &mut Vec<Change>);
+    fn read_value(
+        &self,
+        value: &Value,
+        path: &str,
+        plain: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<T, JsonError>;
+    fn merge_patch(
+        &self,
+        target: &mut T,
+        patch: &Value,
+        path: &str,
+        replace: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<(), JsonError>;
+    fn check_merge_patch(
+        &self,
+        target: &T,
+        patch: &Value,
+        path: &str,
+        replace: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<(), JsonError>;
+    fn bson_path(&self, rest: &str) -> Option<String>;
+    fn bson_schema(&self, plain: Document) -> Document;
+}
+
+impl<T: JsonScalar> ScalarOps<T> for &Probe<T> {
+    fn is_node(&self) -> bool {
+        false
+    }
+
+    fn to_value(&self, value: &T, _render: Render) -> Value {
+        value.to_json_value()
+    }
+
+    fn to_value_with<C: ?Sized>(&self, value: &T, _ctx: &C) -> Value {
+        value.to_json_value()
+    }
+
+    fn serializable(&self, value: &T) -> Value {
+        value.to_json_value()
+    }
+
+    fn diff(&self, old: &T, new: &T, path: &str, changes: &mut Vec<Change>) {
+        push_change(
+            changes,
+            path.to_string(),
+            old.to_json_value(),
+            new.to_json_value(),
+        );
+    }
+
+    fn read_value(
+        &self,
+        value: &Value,
+        path: &str,
+        _plain: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<T, JsonError> {
+        T::from_json_value(value, path)
+    }
+
+    fn merge_patch(
+        &self,
+        target: &mut T,
+        _patch: &Value,
+        _path: &str,
+        replace: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<(), JsonError> {
+        *target = replace()?;
+        Ok(())
+    }
+
+    fn check_merge_patch(
+        &self,
+        _target: &T,
+        _patch: &Value,
+        _path: &str,
+        replace: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<(), JsonError> {
+        replace().map(drop)
+    }
+
+    fn bson_path(&self, _rest: &str) -> Option<String> {
+        None
+    }
+
+    fn bson_schema(&self, plain: Document) -> Document {
+        T::bson_schema(plain)
+    }
+}
+
+pub trait PlainOps<T> {
+    fn is_node(&self) -> bool;
+    fn to_value(&self, value: &T, render: Render) -> Value
+    where
+        T: Serialize;
+    fn to_value_with<C: ?Sized>(&self, value: &T, ctx: &C) -> Value
+    where
+        T: Serialize;
+    fn serializable<'v>(&self, value: &'v T) -> &'v T;
+    fn diff(&self, old: &T, new: &T, path: &str, changes: &mut Vec<Change>)
+    where
+        T: Serialize;
+    fn read_value(
+        &self,
+        value: &Value,
+        path: &str,
+        plain: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<T, JsonError>;
+    fn merge_patch(
+        &self,
+        target: &mut T,
+        patch: &Value,
+        path: &str,
+        replace: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<(), JsonError>;
+    fn check_merge_patch(
+        &self,
+        target: &T,
+        patch: &Value,
+        path: &str,
+        replace: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<(), JsonError>;
+    fn bson_path(&self, rest: &str) -> Option<String>;
+    fn bson_schema(&self, plain: Document) -> Document;
+}
+
+impl<T> PlainOps<T> for Probe<T> {
+    fn is_node(&self) -> bool {
+        false
+    }
+
+    fn to_value(&self, value: &T, _render: Render) -> Value
+    where
+        T: Serialize,
+    {
+        serde_json::json!(value)
+    }
+
+    fn to_value_with<C: ?Sized>(&self, value: &T, _ctx: &C) -> Value
+    where
+        T: Serialize,
+    {
+        serde_json::json!(value)
+    }
+
+    fn serializable<'v>(&self, value: &'v T) -> &'v T {
+        value
+    }
+
+    fn diff(&self, old: &T, new: &T, path: &str, changes: &mut Vec<Change>)
+    where
+        T: Serialize,
+    {
+        push_change(
+            changes,
+            path.to_string(),
+            serde_json::json!(old),
+            serde_json::json!(new),
+        );
+    }
+
+    fn read_value(
+        &self,
+        _value: &Value,
+        _path: &str,
+        plain: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<T, JsonError> {
+        plain()
+    }
+
+    fn merge_patch(
+        &self,
+        target: &mut T,
+        _patch: &Value,
+        _path: &str,
+        replace: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<(), JsonError> {
+        *target = replace()?;
+        Ok(())
+    }
+
+    fn check_merge_patch(
+        &self,
+        _target: &T,
+        _patch: &Value,
+        _path: &str,
+        replace: impl FnOnce() -> Result<T, JsonError>,
+    ) -> Result<(), JsonError> {
+        replace().map(drop)
+    }
+
+    fn bson_path(&self, _rest: &str) -> Option<String> {
+        None
+    }
+
+    fn bson_schema(&self, plain: Document) -> Document {
+        plain
+    }
+}
+
+// Constants can't go through autoref, but an inherent constant of `Probe<T>`
+// still wins over the trait default whenever its bound holds.
+pub trait ProbeConsts {
+    const IS_NODE: bool = false;
+    const HAS_SELECTORS: bool = false;
+}
+
+impl<T> ProbeConsts for Probe<T> {}
+
+impl<T: JsonNode> Probe<T> {
+    pub const IS_NODE: bool = true;
+}
+
+impl<T: Selectable> Probe<T> {
+    pub const HAS_SELECTORS: bool = true;
+}
@@ -0,0 +1,17 @@
+/// One field of a generated `#[json(patch)]` struct.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PatchField<T> {
+    /// The key was not sent, so the field is left alone.
+    #[default]
+    Absent,
+    /// The key was sent as `null`, so an optional field is cleared.
+    Null,
+    /// The key was sent with a value.
+    Value(T),
+}
+
+impl<T> PatchField<T> {
+    pub fn is_absent(&self) -> bool {
+        matches!(self, PatchField::Absent)
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+
+/// A MongoDB dot-notation path to a stored field, like `two._id`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FieldPath(String);
+
+impl FieldPath {
+    pub fn new(path: impl Into<String>) -> Self {
+        FieldPath(path.into())
+    }
+
+    /// Appends `name` to `prefix`, leaving it alone when `prefix` is empty.
+    pub fn join(prefix: &str, name: &str) -> Self {
+        if prefix.is_empty() {
+            FieldPath(name.to_string())
+        } else {
+            FieldPath(format!("{}.{}", prefix, name))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for FieldPath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<FieldPath> for String {
+    fn from(path: FieldPath) -> Self {
+        path.0
+    }
+}
+
+impl PartialEq<str> for FieldPath {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for FieldPath {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+/// One struct field as `fields()` describes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldInfo {
+    /// The Rust field name.
+    pub name: &'static str,
+    /// The key in the `to_json` output.
+    pub key: &'static str,
+    /// The name the field is stored under.
+    pub bson_name: &'static str,
+    /// The field type as written in the struct.
+    pub type_name: &'static str,
+    pub is_option: bool,
+    /// Whether the field is a `Vec`, possibly inside an `Option`.
+    pub is_vec: bool,
+    /// Whether the field holds a `ToJson` type, marked `#[json]` or not.
+    pub nested: bool,
+    /// The field's doc comment, one line per `///` line.
+    pub docs: &'static str,
+}
+
+/// Implemented by every `ToJson` struct.
+pub trait ToJsonFields {
+    /// Names, types and docs of the fields, in declaration order.
+    fn fields() -> &'static [FieldInfo];
+}
+
+/// Implemented by `ToJson` enums and `#[json(mongo)]` structs so nested fields
+/// can hand out their own typed paths below a prefix.
+pub trait ToJsonPaths {
+    type Paths;
+
+    fn paths_with_prefix(prefix: &str) -> Self::Paths;
+}
+
+/// Implemented by `#[json(mongo)]` structs, whose selectors have a method per field.
+#[doc(hidden)]
+pub trait Selectable {}
@@ -0,0 +1,14 @@
+use serde_json::Value;
+
+use crate::JsonError;
+
+/// Reads and writes single values of the `to_json` output by JSON Pointer,
+/// implemented by the `ToJson` derive.
+pub trait JsonPointer {
+    /// The value at `pointer`, converting only the field it points into.
+    fn json_get(&self, pointer: &str) -> Option<Value>;
+
+    /// Sets the value at `pointer`, reading it the way `from_json` reads the
+    /// field it points into.
+    fn json_set(&mut self, pointer: &str, value: Value) -> Result<(), JsonError>;
+}
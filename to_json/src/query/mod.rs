@@ -0,0 +1,286 @@
+use std::any::TypeId;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+use mongodb::bson::{self, doc, Bson, Document};
+use serde::Serialize;
+
+use crate::FieldPath;
+
+fn to_bson<T: Serialize>(value: &T) -> Bson {
+    bson::to_bson(value).expect("Failed to serialize to BSON")
+}
+
+// Lets `null` through on top of whatever the schema already allows.
+pub fn nullable_schema(mut schema: Document) -> Document {
+    let null = Bson::String("null".to_string());
+    if let Ok(values) = schema.get_array_mut("enum") {
+        if !values.contains(&Bson::Null) {
+            values.push(Bson::Null);
+        }
+        return schema;
+    }
+    match schema.get("bsonType").cloned() {
+        Some(Bson::String(bson_type)) => {
+            schema.insert("bsonType", vec![null, Bson::String(bson_type)]);
+            schema
+        }
+        Some(Bson::Array(mut bson_types)) => {
+            if !bson_types.contains(&null) {
+                bson_types.insert(0, null);
+                schema.insert("bsonType", bson_types);
+            }
+            schema
+        }
+        // An empty schema already allows anything.
+        None if schema.is_empty() => schema,
+        _ => doc! { "anyOf": [{ "bsonType": "null" }, schema] },
+    }
+}
+
+thread_local! {
+    static SCHEMAS_IN_PROGRESS: RefCell<Vec<TypeId>> = const { RefCell::new(Vec::new()) };
+}
+
+// Builds the schema of `T`, unless it is already being built further up. That
+// only happens for recursive types, which are left as a plain object there.
+pub fn recursive_schema<T: 'static>(build: impl FnOnce() -> Document) -> Document {
+    let type_id = TypeId::of::<T>();
+    if SCHEMAS_IN_PROGRESS.with(|in_progress| in_progress.borrow().contains(&type_id)) {
+        return doc! { "bsonType": "object" };
+    }
+
+    SCHEMAS_IN_PROGRESS.with(|in_progress| in_progress.borrow_mut().push(type_id));
+    let _in_progress = InProgress;
+    build()
+}
+
+// Pops the type `recursive_schema` pushed, also when `build` panics.
+struct InProgress;
+
+impl Drop for InProgress {
+    fn drop(&mut self) {
+        SCHEMAS_IN_PROGRESS.with(|in_progress| in_progress.borrow_mut().pop());
+    }
+}
+
+/// A MongoDB query filter built from typed field selectors.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter(Document);
+
+impl Filter {
+    pub fn new(document: Document) -> Self {
+        Filter(document)
+    }
+
+    /// Matches documents that pass every filter.
+    pub fn all(filters: impl IntoIterator<Item = Filter>) -> Self {
+        Self::combine("$and", filters)
+    }
+
+    /// Matches documents that pass at least one filter.
+    pub fn any(filters: impl IntoIterator<Item = Filter>) -> Self {
+        Self::combine("$or", filters)
+    }
+
+    pub fn and(self, other: Filter) -> Self {
+        Self::all([self, other])
+    }
+
+    pub fn or(self, other: Filter) -> Self {
+        Self::any([self, other])
+    }
+
+    pub fn as_document(&self) -> &Document {
+        &self.0
+    }
+
+    pub fn into_document(self) -> Document {
+        self.0
+    }
+
+    // Flattens nested uses of the same operator so chained calls stay shallow.
+    fn combine(operator: &str, filters: impl IntoIterator<Item = Filter>) -> Self {
+        let mut clauses = Vec::new();
+        for filter in filters {
+            match filter.0.get_array(operator) {
+                Ok(inner) if filter.0.len() == 1 => clauses.extend(inner.iter().cloned()),
+                _ => clauses.push(Bson::Document(filter.0)),
+            }
+        }
+        Filter(doc! { operator: clauses })
+    }
+}
+
+impl From<Filter> for Document {
+    fn from(filter: Filter) -> Self {
+        filter.0
+    }
+}
+
+/// Conditions on a single stored field holding a `T`.
+pub struct FieldFilter<T> {
+    path: FieldPath,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> FieldFilter<T> {
+    pub fn new(path: FieldPath) -> Self {
+        FieldFilter {
+            path,
+            marker: PhantomData,
+        }
+    }
+
+    fn operator(self, operator: &str, value: Bson) -> Filter {
+        Filter(doc! { self.path.as_str(): { operator: value } })
+    }
+
+    pub fn exists(self, exists: bool) -> Filter {
+        self.operator("$exists", Bson::Boolean(exists))
+    }
+
+    pub fn is_null(self) -> Filter {
+        Filter(doc! { self.path.as_str(): Bson::Null })
+    }
+}
+
+impl<T: Serialize> FieldFilter<T> {
+    pub fn eq(self, value: impl Into<T>) -> Filter {
+        Filter(doc! { self.path.as_str(): to_bson(&value.into()) })
+    }
+
+    pub fn ne(self, value: impl Into<T>) -> Filter {
+        self.operator("$ne", to_bson(&value.into()))
+    }
+
+    pub fn gt(self, value: impl Into<T>) -> Filter {
+        self.operator("$gt", to_bson(&value.into()))
+    }
+
+    pub fn gte(self, value: impl Into<T>) -> Filter {
+        self.operator("$gte", to_bson(&value.into()))
+    }
+
+    pub fn lt(self, value: impl Into<T>) -> Filter {
+        self.operator("$lt", to_bson(&value.into()))
+    }
+
+    pub fn lte(self, value: impl Into<T>) -> Filter {
+        self.operator("$lte", to_bson(&value.into()))
+    }
+
+    pub fn is_in<V: Into<T>>(self, values: impl IntoIterator<Item = V>) -> Filter {
+        let values = values.into_iter().map(|value| to_bson(&value.into()));
+        self.operator("$in", Bson::Array(values.collect()))
+    }
+
+    pub fn not_in<V: Into<T>>(self, values: impl IntoIterator<Item = V>) -> Filter {
+        let values = values.into_iter().map(|value| to_bson(&value.into()));
+        self.operator("$nin", Bson::Array(values.collect()))
+    }
+}
+
+/// Conditions on a stored array whose elements are `T`.
+pub struct ArrayFilter<T> {
+    path: FieldPath,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> ArrayFilter<T> {
+    pub fn new(path: FieldPath) -> Self {
+        ArrayFilter {
+            path,
+            marker: PhantomData,
+        }
+    }
+
+    fn operator(self, operator: &str, value: Bson) -> Filter {
+        Filter(doc! { self.path.as_str(): { operator: value } })
+    }
+
+    pub fn size(self, size: usize) -> Filter {
+        self.operator("$size", Bson::Int64(size as i64))
+    }
+
+    pub fn exists(self, exists: bool) -> Filter {
+        self.operator("$exists", Bson::Boolean(exists))
+    }
+}
+
+impl<T: Serialize> ArrayFilter<T> {
+    /// Matches arrays holding `value` among their elements.
+    pub fn contains(self, value: impl Into<T>) -> Filter {
+        Filter(doc! { self.path.as_str(): to_bson(&value.into()) })
+    }
+
+    pub fn contains_all<V: Into<T>>(self, values: impl IntoIterator<Item = V>) -> Filter {
+        let values = values.into_iter().map(|value| to_bson(&value.into()));
+        self.operator("$all", Bson::Array(values.collect()))
+    }
+
+    pub fn contains_any<V: Into<T>>(self, values: impl IntoIterator<Item = V>) -> Filter {
+        let values = values.into_iter().map(|value| to_bson(&value.into()));
+        self.operator("$in", Bson::Array(values.collect()))
+    }
+}
+
+/// A MongoDB sort specification, keys applied in insertion order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sort(Document);
+
+impl Sort {
+    pub fn then(mut self, other: Sort) -> Self {
+        self.0.extend(other.0);
+        self
+    }
+
+    pub fn as_document(&self) -> &Document {
+        &self.0
+    }
+
+    pub fn into_document(self) -> Document {
+        self.0
+    }
+}
+
+impl From<Sort> for Document {
+    fn from(sort: Sort) -> Self {
+        sort.0
+    }
+}
+
+/// A stored field that can be sorted on.
+pub struct SortField {
+    path: FieldPath,
+}
+
+impl SortField {
+    pub fn new(path: FieldPath) -> Self {
+        SortField { path }
+    }
+
+    pub fn asc(self) -> Sort {
+        Sort(doc! { self.path.as_str(): 1 })
+    }
+
+    pub fn desc(self) -> Sort {
+        Sort(doc! { self.path.as_str(): -1 })
+    }
+}
+
+/// Implemented by every `ToJson` type so nested fields can hand out their own
+/// filter selectors below a prefix.
+pub trait ToJsonFilter {
+    type Filter;
+
+    fn filter_with_prefix(prefix: &str) -> Self::Filter;
+}
+
+/// Implemented by every `ToJson` type so nested fields can hand out their own
+/// sort selectors below a prefix.
+pub trait ToJsonSort {
+    type Sort;
+
+    fn sort_with_prefix(prefix: &str) -> Self::Sort;
+}
@@ -0,0 +1,171 @@
+use mongodb::bson::{doc, Bson, Document};
+use serde_json::Value;
+
+use crate::value::{bson_to_json, document_to_json, json_to_bson, json_to_document};
+use crate::JsonError;
+
+/// Implemented for leaf types whose JSON is not what their `Serialize` impl
+/// writes, so that fields holding them are converted by type whatever the
+/// path they are spelled with.
+pub trait JsonScalar: Sized {
+    fn to_json_value(&self) -> Value;
+    fn from_json_value(value: &Value, path: &str) -> Result<Self, JsonError>;
+
+    /// The schema MongoDB validates the stored value with, `plain` when the
+    /// type leaves it open.
+    fn bson_schema(plain: Document) -> Document {
+        plain
+    }
+}
+
+impl JsonScalar for Document {
+    fn to_json_value(&self) -> Value {
+        document_to_json(self)
+    }
+
+    fn from_json_value(value: &Value, path: &str) -> Result<Self, JsonError> {
+        json_to_document(value, path)
+    }
+
+    fn bson_schema(_plain: Document) -> Document {
+        doc! { "bsonType": "object" }
+    }
+}
+
+impl JsonScalar for Bson {
+    fn to_json_value(&self) -> Value {
+        bson_to_json(self)
+    }
+
+    fn from_json_value(value: &Value, path: &str) -> Result<Self, JsonError> {
+        json_to_bson(value, path)
+    }
+}
+
+#[cfg(any(
+    feature = "chrono",
+    feature = "time",
+    feature = "uuid",
+    feature = "rust_decimal",
+    feature = "url"
+))]
+fn parse_str<T, E: std::fmt::Display>(
+    value: &Value,
+    path: &str,
+    parse: impl FnOnce(&str) -> Result<T, E>,
+) -> Result<T, JsonError> {
+    crate::error::expect_str(value, path)
+        .and_then(|string| parse(string).map_err(|err| JsonError::new(path, err)))
+}
+
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> JsonScalar for chrono::DateTime<Tz>
+where
+    Tz::Offset: std::fmt::Display,
+    chrono::DateTime<Tz>: std::str::FromStr<Err = chrono::ParseError>,
+{
+    fn to_json_value(&self) -> Value {
+        Value::String(self.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true))
+    }
+
+    fn from_json_value(value: &Value, path: &str) -> Result<Self, JsonError> {
+        parse_str(value, path, str::parse)
+    }
+}
+
+#[cfg(feature = "time")]
+impl JsonScalar for time::OffsetDateTime {
+    fn to_json_value(&self) -> Value {
+        Value::String(
+            self.format(&time::format_description::well_known::Rfc3339)
+                .expect("Failed to format OffsetDateTime as RFC 3339"),
+        )
+    }
+
+    fn from_json_value(value: &Value, path: &str) -> Result<Self, JsonError> {
+        parse_str(value, path, |string| {
+            Self::parse(string, &time::format_description::well_known::Rfc3339)
+        })
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl JsonScalar for uuid::Uuid {
+    fn to_json_value(&self) -> Value {
+        Value::String(self.to_string())
+    }
+
+    fn from_json_value(value: &Value, path: &str) -> Result<Self, JsonError> {
+        parse_str(value, path, Self::parse_str)
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl JsonScalar for mongodb::bson::Uuid {
+    fn to_json_value(&self) -> Value {
+        Value::String(self.to_string())
+    }
+
+    fn from_json_value(value: &Value, path: &str) -> Result<Self, JsonError> {
+        parse_str(value, path, |string| Self::parse_str(string))
+    }
+}
+
+// UUID binaries are written as UUID strings, any other subtype as extended JSON.
+#[cfg(feature = "uuid")]
+impl JsonScalar for mongodb::bson::Binary {
+    fn to_json_value(&self) -> Value {
+        match self.to_uuid() {
+            Ok(uuid) => Value::String(uuid.to_string()),
+            Err(_) => Bson::Binary(self.clone()).into_relaxed_extjson(),
+        }
+    }
+
+    fn from_json_value(value: &Value, path: &str) -> Result<Self, JsonError> {
+        match value {
+            Value::Object(_) => match Bson::try_from(value.clone()) {
+                Ok(Bson::Binary(binary)) => Ok(binary),
+                _ => Err(JsonError::new(path, "expected an extended JSON binary")),
+            },
+            _ => parse_str(value, path, |string| mongodb::bson::Uuid::parse_str(string))
+                .map(Self::from_uuid),
+        }
+    }
+
+    fn bson_schema(_plain: Document) -> Document {
+        doc! { "bsonType": "binData" }
+    }
+}
+
+// Written as strings to keep every digit, but plain JSON numbers are read too.
+#[cfg(feature = "rust_decimal")]
+impl JsonScalar for rust_decimal::Decimal {
+    fn to_json_value(&self) -> Value {
+        Value::String(self.to_string())
+    }
+
+    fn from_json_value(value: &Value, path: &str) -> Result<Self, JsonError> {
+        match value {
+            Value::Number(number) => number
+                .to_string()
+                .parse()
+                .map_err(|err| JsonError::new(path, err)),
+            _ => parse_str(value, path, str::parse),
+        }
+    }
+}
+
+#[cfg(feature = "url")]
+impl JsonScalar for url::Url {
+    fn to_json_value(&self) -> Value {
+        Value::String(self.as_str().to_owned())
+    }
+
+    fn from_json_value(value: &Value, path: &str) -> Result<Self, JsonError> {
+        parse_str(value, path, Self::parse)
+    }
+
+    fn bson_schema(_plain: Document) -> Document {
+        doc! { "bsonType": "string" }
+    }
+}
@@ -0,0 +1,26 @@
+use serde::{Serialize, Serializer};
+
+/// Serializes a value the way `to_json` writes it, returned by the `as_json`
+/// method `#[json(serialize)]` generates.
+///
+/// The type's own `Serialize` impl, and so what the mongodb driver stores,
+/// stays whatever serde derives.
+pub struct AsJson<'a, T: ?Sized>(&'a T);
+
+impl<'a, T: ?Sized> AsJson<'a, T> {
+    #[doc(hidden)]
+    pub fn new(value: &'a T) -> Self {
+        AsJson(value)
+    }
+}
+
+impl<T: SerializeJson + ?Sized> Serialize for AsJson<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_json(serializer)
+    }
+}
+
+/// Streams the `to_json` output, implemented by the derive for `AsJson`.
+pub trait SerializeJson {
+    fn serialize_json<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+}
@@ -0,0 +1,91 @@
+use mongodb::bson::{Bson, Document};
+use serde_json::{Map, Value};
+
+use crate::JsonError;
+
+/// Whether `value` is an empty string, array or object, as left out by `omit_empty`.
+pub fn is_empty_value(value: &Value) -> bool {
+    match value {
+        Value::String(string) => string.is_empty(),
+        Value::Array(items) => items.is_empty(),
+        Value::Object(object) => object.is_empty(),
+        _ => false,
+    }
+}
+
+/// Splits the first reference token off a JSON Pointer, unescaped, from the
+/// rest of it: `/a~1b/c` gives `a/b` and `/c`.
+pub fn split_pointer(pointer: &str) -> Option<(String, &str)> {
+    let pointer = pointer.strip_prefix('/')?;
+    let (token, rest) = match pointer.find('/') {
+        Some(index) => pointer.split_at(index),
+        None => (pointer, ""),
+    };
+    Some((token.replace("~1", "/").replace("~0", "~"), rest))
+}
+
+/// The array index a JSON Pointer reference token names. RFC 6901 only allows
+/// digits without leading zeros, so `+1` and `01` name nothing.
+pub fn array_index(token: &str) -> Option<usize> {
+    let digits = !token.is_empty() && token.bytes().all(|byte| byte.is_ascii_digit());
+    if !digits || (token.len() > 1 && token.starts_with('0')) {
+        return None;
+    }
+    token.parse().ok()
+}
+
+/// Sets the JSON Pointer `pointer` inside `target` to `value`. A missing last
+/// key is added when its parent is an object; `false` when there is no place.
+pub fn pointer_set(target: &mut Value, pointer: &str, value: Value) -> bool {
+    if let Some(place) = target.pointer_mut(pointer) {
+        *place = value;
+        return true;
+    }
+    let (parent, token) = match pointer.rfind('/') {
+        Some(index) => (&pointer[..index], &pointer[index + 1..]),
+        None => return false,
+    };
+    match target.pointer_mut(parent) {
+        Some(Value::Object(object)) => {
+            object.insert(token.replace("~1", "/").replace("~0", "~"), value);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Relaxed extended JSON, except that ObjectIds and dates are written the way
+/// `to_json` writes those fields: as a hex string and an RFC 3339 string.
+pub fn bson_to_json(bson: &Bson) -> Value {
+    match bson {
+        Bson::ObjectId(oid) => Value::String(oid.to_hex()),
+        Bson::DateTime(date) => match date.try_to_rfc3339_string() {
+            Ok(string) => Value::String(string),
+            Err(_) => bson.clone().into_relaxed_extjson(),
+        },
+        Bson::Document(document) => document_to_json(document),
+        Bson::Array(items) => Value::Array(items.iter().map(bson_to_json).collect()),
+        _ => bson.clone().into_relaxed_extjson(),
+    }
+}
+
+pub fn document_to_json(document: &Document) -> Value {
+    Value::Object(
+        document
+            .iter()
+            .map(|(key, bson)| (key.clone(), bson_to_json(bson)))
+            .collect::<Map<_, _>>(),
+    )
+}
+
+/// Reads extended JSON back; ObjectIds and dates written as strings stay strings.
+pub fn json_to_bson(value: &Value, path: &str) -> Result<Bson, JsonError> {
+    Bson::try_from(value.clone()).map_err(|err| JsonError::new(path, err))
+}
+
+pub fn json_to_document(value: &Value, path: &str) -> Result<Document, JsonError> {
+    match json_to_bson(value, path)? {
+        Bson::Document(document) => Ok(document),
+        _ => Err(JsonError::new(path, "expected an object")),
+    }
+}